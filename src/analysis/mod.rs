@@ -0,0 +1,678 @@
+/// steganalysis of inbound images: structural checks on the chunks and statistical
+/// tests (chi-square and RS) on the least significant bits of the decoded samples.
+/// every finding carries a suspicion score from 0 (nothing unusual) to 100 (almost certain).
+use flate2::read::ZlibDecoder;
+use std::{fmt, io::Read};
+
+use crate::{
+    chunk::Chunk,
+    compression::COMPRESSED_MAGIC,
+    crypto::{PASSPHRASE_MAGIC, RECIPIENT_MAGIC},
+    image::{get_sample, ColorType, ImageData},
+    payload::{FILE_MAGIC, SEQUENCE_MAGIC},
+    png::Png,
+    signature::SIGNATURE_CHUNK_TYPE,
+    Error, Result,
+};
+
+/// chunk types defined by the PNG specification and its registered extensions
+const KNOWN_CHUNK_TYPES: [&str; 31] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCv",
+    "cLLi", "tEXt", "zTXt", "iTXt", "bKGD", "hIST", "pHYs", "sPLT", "eXIf", "tIME", "acTL", "fcTL",
+    "fdAT", "oFFs", "pCAL", "sCAL", "sTER", "gIFg", "dSIG",
+];
+
+/// chunks whose data is compressed by design, high entropy is expected there
+const COMPRESSED_CHUNK_TYPES: [&str; 4] = ["zTXt", "iCCP", "IDAT", "fdAT"];
+
+/// text chunks larger than this are reported
+const LARGE_TEXT_LEN: usize = 1024;
+
+/// the entropy estimate is meaningless for shorter chunks
+const ENTROPY_MIN_LEN: usize = 256;
+
+/// bits per byte above which ancillary chunk data looks compressed or encrypted
+const HIGH_ENTROPY: f64 = 7.0;
+
+/// the chi-square test of a part of the image needs at least this many samples
+const CHI_SQUARE_MIN_SAMPLES: usize = 4096;
+
+/// upper bound when inflating IDAT, past the expected size the rest is only counted
+const INFLATE_SLACK: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// 0 to 100
+    pub score: u8,
+    /// the chunk or test the finding is about
+    pub subject: String,
+    pub detail: String,
+}
+
+impl Finding {
+    fn new<S: Into<String>, D: Into<String>>(score: u8, subject: S, detail: D) -> Finding {
+        Finding {
+            score,
+            subject: subject.into(),
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:3}] {}: {}", self.score, self.subject, self.detail)
+    }
+}
+
+/// run every check on the file bytes, the findings are sorted by descending score
+pub fn analyze(bytes: &[u8]) -> Result<Vec<Finding>> {
+    let end = iend_end(bytes)?;
+    let (png, problems) = Png::parse(&bytes[..end])?;
+
+    // a CRC that was left wrong on purpose is a cheap way to mark a tampered chunk
+    let mut findings: Vec<Finding> = problems
+        .into_iter()
+        .map(|problem| Finding::new(70, "CRC", problem))
+        .collect();
+    let mut chunks: Vec<&Chunk> = png.chunks().iter().collect();
+    let trailing = &bytes[end..];
+    let trailing_chunks = parse_chunks(trailing);
+    if let Some(trailing_chunks) = &trailing_chunks {
+        let names: Vec<String> = trailing_chunks
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        findings.push(Finding::new(
+            90,
+            "after IEND",
+            format!("chunk(s) {} follow the IEND chunk", names.join(", ")),
+        ));
+        chunks.extend(trailing_chunks);
+    } else if !trailing.is_empty() {
+        findings.push(Finding::new(
+            90,
+            "after IEND",
+            format!("{} bytes follow the IEND chunk", trailing.len()),
+        ));
+    }
+    for chunk in chunks {
+        findings.extend(check_chunk(chunk));
+    }
+    match ImageData::decode(&png) {
+        Ok(image) => {
+            findings.extend(check_idat(&png, &image));
+            findings.push(chi_square(&image));
+            findings.extend(rs_analysis(&image));
+        }
+        Err(e) => findings.push(Finding::new(
+            50,
+            "IDAT",
+            format!("the pixel data can not be decoded: {}", e),
+        )),
+    }
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.score));
+    Ok(findings)
+}
+
+/// offset just past the IEND chunk, found by walking the chunk lengths
+fn iend_end(bytes: &[u8]) -> Result<usize> {
+    if bytes.len() < 8 {
+        return Err(Error::from("the file is too short to be a png"));
+    }
+    let mut pivot = 8;
+    while pivot + 12 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[pivot..pivot + 4].try_into().unwrap()) as usize;
+        let end = pivot
+            .checked_add(length + 12)
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| Error::from(format!("the chunk at byte {} is truncated", pivot)))?;
+        if &bytes[pivot + 4..pivot + 8] == b"IEND" {
+            return Ok(end);
+        }
+        pivot = end;
+    }
+    Err(Error::from("missing IEND chunk"))
+}
+
+/// the bytes as a sequence of valid chunks, `None` when they are empty or anything else
+fn parse_chunks(mut bytes: &[u8]) -> Option<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    while bytes.len() >= 12 {
+        let length = u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as usize;
+        let end = length.checked_add(12).filter(|end| *end <= bytes.len())?;
+        chunks.push(Chunk::try_from(&bytes[..end]).ok()?);
+        bytes = &bytes[end..];
+    }
+    (bytes.is_empty() && !chunks.is_empty()).then_some(chunks)
+}
+
+fn check_chunk(chunk: &Chunk) -> Vec<Finding> {
+    let chunk_type = chunk.chunk_type();
+    let name = chunk_type.to_string();
+    let data = chunk.data();
+    let mut findings = Vec::new();
+
+    if let Some(kind) = payload_kind(data) {
+        findings.push(Finding::new(
+            95,
+            &name,
+            format!("holds a png_info {} ({} bytes)", kind, data.len()),
+        ));
+    } else if name == SIGNATURE_CHUNK_TYPE {
+        findings.push(Finding::new(10, &name, "png_info signature chunk"));
+    } else if !KNOWN_CHUNK_TYPES.contains(&name.as_str()) {
+        let (score, kind) = if chunk_type.is_public() {
+            (40, "unknown public")
+        } else {
+            (60, "private")
+        };
+        findings.push(Finding::new(
+            score,
+            &name,
+            format!("{} chunk of {} bytes", kind, data.len()),
+        ));
+    }
+
+    if ["tEXt", "zTXt", "iTXt"].contains(&name.as_str()) && data.len() > LARGE_TEXT_LEN {
+        let score = if data.len() > 16 * LARGE_TEXT_LEN {
+            70
+        } else {
+            40
+        };
+        findings.push(Finding::new(
+            score,
+            &name,
+            format!("text chunk of {} bytes", data.len()),
+        ));
+    }
+
+    if !chunk_type.is_critical()
+        && data.len() >= ENTROPY_MIN_LEN
+        && !COMPRESSED_CHUNK_TYPES.contains(&name.as_str())
+        && !is_compressed_itxt(&name, data)
+    {
+        let entropy = entropy(data);
+        if entropy > HIGH_ENTROPY {
+            // 7 bits per byte scores 50, a random looking 8 scores 90
+            let score = 50.0 + (entropy - HIGH_ENTROPY) * 40.0;
+            findings.push(Finding::new(
+                score.min(90.0) as u8,
+                &name,
+                format!(
+                    "{:.2} bits of entropy per byte, looks compressed or encrypted",
+                    entropy
+                ),
+            ));
+        }
+    }
+    findings
+}
+
+fn payload_kind(data: &[u8]) -> Option<&'static str> {
+    let magic = data.get(0..4)?;
+    [
+        (PASSPHRASE_MAGIC, "passphrase envelope"),
+        (RECIPIENT_MAGIC, "recipient envelope"),
+        (FILE_MAGIC, "file payload"),
+        (SEQUENCE_MAGIC, "split payload part"),
+        (COMPRESSED_MAGIC, "compressed payload"),
+    ]
+    .iter()
+    .find(|(known, _)| magic == known)
+    .map(|(_, kind)| *kind)
+}
+
+fn is_compressed_itxt(name: &str, data: &[u8]) -> bool {
+    name == "iTXt"
+        && data
+            .iter()
+            .position(|byte| *byte == 0)
+            .and_then(|keyword_end| data.get(keyword_end + 1))
+            == Some(&1)
+}
+
+/// Shannon entropy of the bytes in bits per byte
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let total = data.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// data hidden inside the IDAT stream: after the end of the zlib stream, extra scanlines,
+/// or a compressed stream larger than the pixels themselves
+fn check_idat(png: &Png, image: &ImageData) -> Vec<Finding> {
+    let compressed: Vec<u8> = png
+        .chunks_by_type("IDAT")
+        .iter()
+        .flat_map(|chunk| chunk.data().iter().copied())
+        .collect();
+    let Ok(raw_len) = image.header.raw_len() else {
+        return Vec::new();
+    };
+    let mut findings = Vec::new();
+
+    let mut decoder = ZlibDecoder::new(compressed.as_slice());
+    let mut raw = Vec::new();
+    if (&mut decoder)
+        .take((raw_len + INFLATE_SLACK) as u64)
+        .read_to_end(&mut raw)
+        .is_ok()
+    {
+        let consumed = decoder.total_in() as usize;
+        if raw.len() > raw_len {
+            findings.push(Finding::new(
+                80,
+                "IDAT",
+                format!(
+                    "inflates to {} bytes or more, the image needs {}",
+                    raw.len(),
+                    raw_len
+                ),
+            ));
+        } else if consumed < compressed.len() {
+            findings.push(Finding::new(
+                80,
+                "IDAT",
+                format!(
+                    "{} bytes follow the end of the zlib stream",
+                    compressed.len() - consumed
+                ),
+            ));
+        }
+    }
+    // even stored deflate blocks only add 5 bytes per 65535 and the zlib wrapper
+    let stored_len = raw_len + raw_len.div_ceil(65535) * 5 + 6;
+    if compressed.len() > stored_len {
+        findings.push(Finding::new(
+            60,
+            "IDAT",
+            format!(
+                "{} compressed bytes for {} bytes of scanlines",
+                compressed.len(),
+                raw_len
+            ),
+        ));
+    }
+    findings
+}
+
+/// the color samples of every scanline by channel, alpha and fully transparent pixels
+/// excluded since embedding tools usually leave those alone
+fn color_rows(image: &ImageData) -> Vec<Vec<Vec<u16>>> {
+    let header = &image.header;
+    let channels = header.channels();
+    let has_alpha = header.color_type.has_alpha();
+    let colors = if has_alpha { channels - 1 } else { channels };
+    let mut rows = Vec::new();
+    for sub in image.passes.iter().filter(|sub| !sub.data.is_empty()) {
+        let row_len = header.row_len(sub.width);
+        for line in sub.data.chunks(row_len) {
+            let visible: Vec<usize> = (0..sub.width as usize)
+                .filter(|x| {
+                    !has_alpha || get_sample(line, x * channels + colors, header.bit_depth) != 0
+                })
+                .collect();
+            rows.push(
+                (0..colors)
+                    .map(|channel| {
+                        visible
+                            .iter()
+                            .map(|x| get_sample(line, x * channels + channel, header.bit_depth))
+                            .collect()
+                    })
+                    .collect(),
+            );
+        }
+    }
+    rows
+}
+
+/// Westfeld and Pfitzmann's test: LSB embedding equalizes the counts of each pair of
+/// values differing only in the lowest bit. the p-value of the pairs being that equal is
+/// computed for the whole image and for its first part
+fn chi_square(image: &ImageData) -> Finding {
+    let samples: Vec<u16> = color_rows(image)
+        .into_iter()
+        .flat_map(|row| {
+            // interleave the channels back into scanline order
+            let width = row.first().map_or(0, |channel| channel.len());
+            (0..width)
+                .flat_map(|x| row.iter().map(|channel| channel[x]).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let whole = chi_square_probability(&samples);
+    let mut detail = format!(
+        "p = {:.3} for all {} samples that the pairs of values are equalized",
+        whole,
+        samples.len()
+    );
+    let mut probability = whole;
+    // a sequential payload only fills the start, but small samples are too noisy
+    if samples.len() / 10 >= CHI_SQUARE_MIN_SAMPLES {
+        let first = chi_square_probability(&samples[..samples.len() / 10]);
+        detail += &format!(", {:.3} for the first tenth", first);
+        probability = probability.max(first);
+    }
+    // clean images scatter over the lower half, embedded ones sit close to 1
+    let score = ((probability - 0.5) * 200.0).clamp(0.0, 100.0);
+    Finding::new(score.round() as u8, "chi-square", detail)
+}
+
+fn chi_square_probability(samples: &[u16]) -> f64 {
+    let mut histogram = vec![0usize; u16::MAX as usize + 1];
+    for sample in samples {
+        histogram[*sample as usize] += 1;
+    }
+    let mut statistic = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        // sparse categories make the statistic unreliable
+        if expected <= 4.0 {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    upper_incomplete_gamma((categories - 1) as f64 / 2.0, statistic / 2.0)
+}
+
+/// regularized upper incomplete gamma function Q(a, x), the chi-square survival function
+fn upper_incomplete_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let ln_prefix = a * x.ln() - x - ln_gamma(a);
+    if x < a + 1.0 {
+        // series of the lower function
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..1000 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        (1.0 - sum * ln_prefix.exp()).clamp(0.0, 1.0)
+    } else {
+        // Lentz's continued fraction of the upper function
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (ln_prefix.exp() * h).clamp(0.0, 1.0)
+    }
+}
+
+/// Lanczos approximation of ln Γ(x) for x > 0
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let mut y = x;
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000000000190015;
+    for coefficient in COEFFICIENTS {
+        y += 1.0;
+        series += coefficient / y;
+    }
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// Fridrich's RS analysis: groups of four neighbouring samples are regular or singular
+/// depending on whether flipping the LSBs under a mask makes them noisier. LSB embedding
+/// moves the counts for the flip and the shifted flip apart, which estimates the share
+/// of samples carrying payload. palette indexes have no meaningful neighbourhood.
+fn rs_analysis(image: &ImageData) -> Option<Finding> {
+    if image.header.color_type == ColorType::Indexed {
+        return None;
+    }
+    let groups: Vec<[i32; 4]> = color_rows(image)
+        .iter()
+        .flatten()
+        .flat_map(|channel| {
+            channel
+                .chunks_exact(4)
+                .map(|group| [0, 1, 2, 3].map(|i| group[i] as i32))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    if groups.len() < 100 {
+        return None;
+    }
+    let flipped: Vec<[i32; 4]> = groups.iter().map(|group| group.map(|x| x ^ 1)).collect();
+
+    let (rm, sm, rn, sn) = rs_counts(&groups);
+    let (rm1, sm1, rn1, sn1) = rs_counts(&flipped);
+    let (d0, d1, n0, n1) = (rm - sm, rm1 - sm1, rn - sn, rn1 - sn1);
+    let a = 2.0 * (d1 + d0);
+    let b = n0 - n1 - d1 - 3.0 * d0;
+    let c = d0 - n0;
+    let root = if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            0.0
+        } else {
+            -c / b
+        }
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            0.0
+        } else {
+            let r1 = (-b + discriminant.sqrt()) / (2.0 * a);
+            let r2 = (-b - discriminant.sqrt()) / (2.0 * a);
+            if r1.abs() < r2.abs() {
+                r1
+            } else {
+                r2
+            }
+        }
+    };
+    let rate = (root / (root - 0.5)).clamp(0.0, 1.0);
+    // clean images usually estimate a few percent, half the samples changed scores 100
+    Some(Finding::new(
+        (rate * 200.0).min(100.0).round() as u8,
+        "RS analysis",
+        format!(
+            "estimated {:.1}% of the samples carry payload bits",
+            rate * 100.0
+        ),
+    ))
+}
+
+/// shares of regular and singular groups under the mask 0110 and its negative
+fn rs_counts(groups: &[[i32; 4]]) -> (f64, f64, f64, f64) {
+    const MASK: [bool; 4] = [false, true, true, false];
+    let smoothness =
+        |group: &[i32; 4]| -> i32 { group.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum() };
+    let flip = |group: &[i32; 4], f: fn(i32) -> i32| -> [i32; 4] {
+        let mut out = *group;
+        for (value, masked) in out.iter_mut().zip(MASK) {
+            if masked {
+                *value = f(*value);
+            }
+        }
+        out
+    };
+    let (mut rm, mut sm, mut rn, mut sn) = (0, 0, 0, 0);
+    for group in groups {
+        let original = smoothness(group);
+        let positive = smoothness(&flip(group, |x| x ^ 1));
+        let negative = smoothness(&flip(group, |x| ((x + 1) ^ 1) - 1));
+        match positive.cmp(&original) {
+            std::cmp::Ordering::Greater => rm += 1,
+            std::cmp::Ordering::Less => sm += 1,
+            std::cmp::Ordering::Equal => {}
+        }
+        match negative.cmp(&original) {
+            std::cmp::Ordering::Greater => rn += 1,
+            std::cmp::Ordering::Less => sn += 1,
+            std::cmp::Ordering::Equal => {}
+        }
+    }
+    let total = groups.len() as f64;
+    (
+        rm as f64 / total,
+        sm as f64 / total,
+        rn as f64 / total,
+        sn as f64 / total,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk_type::ChunkType,
+        stego::{self, LsbOptions},
+    };
+    use rand_chacha::{
+        rand_core::{RngCore, SeedableRng},
+        ChaCha20Rng,
+    };
+    use std::str::FromStr;
+
+    const DICE: &[u8] = &crate::png::tests::PNG_FILE;
+
+    fn score_of(findings: &[Finding], subject: &str) -> u8 {
+        findings
+            .iter()
+            .find(|finding| finding.subject == subject)
+            .map(|finding| finding.score)
+            .unwrap_or_else(|| panic!("no {} finding in {:?}", subject, findings))
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let findings = analyze(DICE).unwrap();
+        assert!(score_of(&findings, "chi-square") < 50, "{:?}", findings);
+        assert!(score_of(&findings, "RS analysis") < 50, "{:?}", findings);
+    }
+
+    #[test]
+    fn test_lsb_embedding() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let mut image = ImageData::decode(&png).unwrap();
+        let options = LsbOptions::default();
+        let mut payload = vec![0u8; stego::capacity(&image, &options).unwrap()];
+        ChaCha20Rng::from_seed([7; 32]).fill_bytes(&mut payload);
+        stego::embed(&mut image, &payload, &options).unwrap();
+        png.replace_idat(&image.encode().unwrap(), 8192).unwrap();
+
+        let findings = analyze(&png.as_bytes()).unwrap();
+        assert!(score_of(&findings, "chi-square") > 90, "{:?}", findings);
+        assert!(score_of(&findings, "RS analysis") > 50, "{:?}", findings);
+    }
+
+    #[test]
+    fn test_structure() {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.insert_before_iend(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"hello".to_vec(),
+        ));
+        let mut noise = vec![0u8; 2048];
+        ChaCha20Rng::from_seed([1; 32]).fill_bytes(&mut noise);
+        png.insert_before_iend(Chunk::new(ChunkType::from_str("teSt").unwrap(), noise));
+        let mut bytes = png.as_bytes();
+        bytes.extend_from_slice(b"trailing secret");
+
+        let findings = analyze(&bytes).unwrap();
+        assert_eq!(score_of(&findings, "after IEND"), 90);
+        assert_eq!(score_of(&findings, "ruSt"), 60);
+        let noisy: Vec<&Finding> = findings
+            .iter()
+            .filter(|finding| finding.subject == "teSt")
+            .collect();
+        assert_eq!(noisy.len(), 2);
+        assert!(noisy.iter().any(|finding| finding.score > 80));
+
+        let mut png = Png::try_from(DICE).unwrap();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("hiDe").unwrap(),
+            b"after the end".to_vec(),
+        ));
+        let findings = analyze(&png.as_bytes()).unwrap();
+        assert!(findings
+            .iter()
+            .any(|finding| finding.subject == "after IEND" && finding.detail.contains("hiDe")));
+        assert_eq!(score_of(&findings, "hiDe"), 60);
+    }
+
+    #[test]
+    fn test_idat_trailing_data() {
+        let mut png = Png::try_from(DICE).unwrap();
+        let image = ImageData::decode(&png).unwrap();
+        let stream = [image.encode().unwrap(), b"hidden".to_vec()].concat();
+        png.replace_idat(&stream, 8192).unwrap();
+        let findings = analyze(&png.as_bytes()).unwrap();
+        assert_eq!(score_of(&findings, "IDAT"), 80);
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut png = Png::try_from(DICE).unwrap();
+        png.insert_before_iend(Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            b"Comment\0hi".to_vec(),
+        ));
+        let mut bytes = png.as_bytes();
+        let crc = bytes.len() - 12 - 4;
+        bytes[crc] ^= 1;
+        let findings = analyze(&bytes).unwrap();
+        assert_eq!(score_of(&findings, "CRC"), 70);
+        assert!(findings
+            .iter()
+            .any(|finding| finding.detail.starts_with("CRC mismatch in tEXt 0 at byte")));
+    }
+
+    #[test]
+    fn test_upper_incomplete_gamma() {
+        // chi-square survival function for 2 and 10 degrees of freedom
+        assert!((upper_incomplete_gamma(1.0, 1.0) - (-1.0f64).exp()).abs() < 1e-9);
+        assert!((upper_incomplete_gamma(5.0, 9.1535 / 2.0) - 0.5175).abs() < 1e-3);
+        assert!(upper_incomplete_gamma(5.0, 100.0) < 1e-10);
+    }
+}
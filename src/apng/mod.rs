@@ -0,0 +1,926 @@
+/// animated PNG: acTL announces the animation, each frame starts with fcTL and its image data is
+/// in IDAT for the default image or in fdAT chunks. fcTL and fdAT share one sequence counter.
+use std::fmt;
+
+use crate::{
+    chunk::Chunk,
+    image::{Header, ImageData},
+    png::Png,
+    Error, Result,
+};
+
+/// types of the chunks an APNG adds
+pub const ANIMATION_CHUNK_TYPES: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+/// acTL: the number of frames and how many times to play them, 0 for forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    pub num_plays: u32,
+}
+
+impl AnimationControl {
+    pub fn parse(data: &[u8]) -> Result<AnimationControl> {
+        if data.len() != 8 {
+            return Err(Error::from(format!(
+                "acTL must be 8 bytes, found {}",
+                data.len()
+            )));
+        }
+        let control = AnimationControl {
+            num_frames: u32::from_be_bytes(data[0..4].try_into().unwrap()),
+            num_plays: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+        };
+        if control.num_frames == 0 {
+            return Err(Error::from("acTL announces 0 frames"));
+        }
+        Ok(control)
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [self.num_frames.to_be_bytes(), self.num_plays.to_be_bytes()].concat()
+    }
+}
+
+/// what happens to the frame region before the next frame is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    /// leave it as it is
+    None,
+    /// clear it to transparent black
+    Background,
+    /// restore what was there before the frame
+    Previous,
+}
+
+/// how the frame is drawn onto the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    /// replace the region
+    Source,
+    /// alpha composite over the region
+    Over,
+}
+
+impl fmt::Display for DisposeOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DisposeOp::None => "none",
+            DisposeOp::Background => "background",
+            DisposeOp::Previous => "previous",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for BlendOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BlendOp::Source => "source",
+            BlendOp::Over => "over",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// fcTL: the region, delay and operations of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    /// 0 means 100
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    pub fn parse(data: &[u8]) -> Result<FrameControl> {
+        if data.len() != 26 {
+            return Err(Error::from(format!(
+                "fcTL must be 26 bytes, found {}",
+                data.len()
+            )));
+        }
+        let be_u32 = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+        let be_u16 = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        let dispose_op = match data[24] {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            op => return Err(Error::from(format!("unknown fcTL dispose op {}", op))),
+        };
+        let blend_op = match data[25] {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            op => return Err(Error::from(format!("unknown fcTL blend op {}", op))),
+        };
+        Ok(FrameControl {
+            sequence_number: be_u32(0),
+            width: be_u32(4),
+            height: be_u32(8),
+            x_offset: be_u32(12),
+            y_offset: be_u32(16),
+            delay_num: be_u16(20),
+            delay_den: be_u16(22),
+            dispose_op,
+            blend_op,
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [
+            self.sequence_number.to_be_bytes().as_ref(),
+            &self.width.to_be_bytes(),
+            &self.height.to_be_bytes(),
+            &self.x_offset.to_be_bytes(),
+            &self.y_offset.to_be_bytes(),
+            &self.delay_num.to_be_bytes(),
+            &self.delay_den.to_be_bytes(),
+            &[self.dispose_op as u8, self.blend_op as u8],
+        ]
+        .concat()
+    }
+
+    /// how long the frame is shown in seconds
+    pub fn delay(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 / den as f64
+    }
+
+    /// the region must be non empty and inside the image
+    fn check_region(&self, header: &Header) -> Result<()> {
+        let fits = |offset: u32, len: u32, limit: u32| {
+            len > 0 && offset.checked_add(len).is_some_and(|end| end <= limit)
+        };
+        if !fits(self.x_offset, self.width, header.width)
+            || !fits(self.y_offset, self.height, header.height)
+        {
+            return Err(Error::from(format!(
+                "frame {}x{} at ({}, {}) is outside the {}x{} image",
+                self.width, self.height, self.x_offset, self.y_offset, header.width, header.height
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// a frame with its compressed image data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub control: FrameControl,
+    /// the zlib stream of the frame, the fdAT sequence numbers removed
+    pub data: Vec<u8>,
+    /// the default image of IDAT is this frame
+    pub is_default_image: bool,
+    /// IDAT or fdAT chunks holding the data
+    pub data_chunks: usize,
+}
+
+/// acTL with the frames of an APNG in display order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    pub control: AnimationControl,
+    pub frames: Vec<Frame>,
+}
+
+impl Animation {
+    /// `None` when the png is not animated. sequence numbers must count up from 0 in file
+    /// order, and the frames must match acTL and fit in IHDR.
+    pub fn from_png(png: &Png) -> Result<Option<Animation>> {
+        let Some(actl) = png.chunk_by_type("acTL") else {
+            if png.chunk_by_type("fcTL").is_some() || png.chunk_by_type("fdAT").is_some() {
+                return Err(Error::from("fcTL or fdAT without acTL"));
+            }
+            return Ok(None);
+        };
+        let control = AnimationControl::parse(actl.data())?;
+        let header = Header::from_png(png)?;
+
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut next_sequence = 0;
+        let mut check_sequence = |sequence_number: u32| -> Result<()> {
+            if sequence_number != next_sequence {
+                return Err(Error::from(format!(
+                    "sequence number {} found where {} was expected",
+                    sequence_number, next_sequence
+                )));
+            }
+            next_sequence += 1;
+            Ok(())
+        };
+        let mut seen_idat = false;
+        for chunk in png.chunks() {
+            match chunk.chunk_type().to_string().as_str() {
+                "acTL" if seen_idat => return Err(Error::from("acTL must come before IDAT")),
+                "fcTL" => {
+                    let frame_control = FrameControl::parse(chunk.data())?;
+                    check_sequence(frame_control.sequence_number)?;
+                    frame_control.check_region(&header)?;
+                    frames.push(Frame {
+                        control: frame_control,
+                        data: Vec::new(),
+                        is_default_image: !seen_idat,
+                        data_chunks: 0,
+                    });
+                }
+                "IDAT" => {
+                    seen_idat = true;
+                    if let Some(frame) = frames.last_mut().filter(|frame| frame.is_default_image) {
+                        frame.data.extend_from_slice(chunk.data());
+                        frame.data_chunks += 1;
+                    }
+                }
+                "fdAT" => {
+                    let data = chunk.data();
+                    if data.len() < 4 {
+                        return Err(Error::from("fdAT is too short for its sequence number"));
+                    }
+                    check_sequence(u32::from_be_bytes(data[0..4].try_into().unwrap()))?;
+                    match frames.last_mut() {
+                        Some(frame) if !frame.is_default_image => {
+                            frame.data.extend_from_slice(&data[4..]);
+                            frame.data_chunks += 1;
+                        }
+                        _ => return Err(Error::from("fdAT without an fcTL for its frame")),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(first) = frames.first().filter(|frame| frame.is_default_image) {
+            let control = &first.control;
+            if (
+                control.width,
+                control.height,
+                control.x_offset,
+                control.y_offset,
+            ) != (header.width, header.height, 0, 0)
+            {
+                return Err(Error::from(
+                    "the frame of the default image must cover the whole image",
+                ));
+            }
+        }
+        if let Some(index) = frames.iter().position(|frame| frame.data_chunks == 0) {
+            return Err(Error::from(format!("frame {} has no image data", index)));
+        }
+        if frames.len() != control.num_frames as usize {
+            return Err(Error::from(format!(
+                "acTL announces {} frames, found {}",
+                control.num_frames,
+                frames.len()
+            )));
+        }
+        Ok(Some(Animation { control, frames }))
+    }
+
+    /// decode every frame and draw it onto the canvas with its blend and dispose operations,
+    /// the canvas after each frame is drawn is returned as a full RGBA image
+    pub fn compose(&self, png: &Png) -> Result<Vec<ImageData>> {
+        let header = Header::from_png(png)?;
+        let palette = png.palette()?;
+        let transparency = png.transparency()?;
+        let decode = |i: usize, frame: &Frame| {
+            let frame_header = Header {
+                width: frame.control.width,
+                height: frame.control.height,
+                ..header
+            };
+            ImageData::decode_stream(frame_header, &frame.data, transparency.clone())
+                .and_then(|image| image.to_rgba(palette.as_deref()))
+                .map_err(|e| Error::from(format!("frame {}: {}", i, e)))
+        };
+
+        // the canvas is only allocated once the first frame, which must cover it, has been
+        // inflated, so a forged IHDR size is backed by real image data
+        let first = self
+            .frames
+            .first()
+            .ok_or_else(|| Error::from("the animation has no frames"))?;
+        let control = &first.control;
+        if (
+            control.width,
+            control.height,
+            control.x_offset,
+            control.y_offset,
+        ) != (header.width, header.height, 0, 0)
+        {
+            return Err(Error::from("the first frame must cover the whole image"));
+        }
+        let mut first_pixels = Some(decode(0, first)?);
+        let (width, height) = (header.width as usize, header.height as usize);
+        let size = width
+            .checked_mul(height)
+            .ok_or_else(|| Error::from("IHDR size overflows"))?;
+        let mut canvas = vec![[0u16; 4]; size];
+        let mut composed = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            let control = &frame.control;
+            let pixels = match first_pixels.take() {
+                Some(pixels) => pixels,
+                None => decode(i, frame)?,
+            };
+
+            let region: Vec<(usize, usize)> = (0..control.height as usize)
+                .flat_map(|y| (0..control.width as usize).map(move |x| (x, y)))
+                .collect();
+            let at = |(x, y): (usize, usize)| {
+                (control.y_offset as usize + y) * width + control.x_offset as usize + x
+            };
+            let previous: Vec<[u16; 4]> = region.iter().map(|&xy| canvas[at(xy)]).collect();
+            for &(x, y) in &region {
+                let source = pixels[y * control.width as usize + x];
+                let target = &mut canvas[at((x, y))];
+                *target = match control.blend_op {
+                    BlendOp::Source => source,
+                    BlendOp::Over => over(source, *target),
+                };
+            }
+            composed.push(ImageData::from_rgba(
+                header.width,
+                header.height,
+                &canvas,
+                header.bit_depth == 16,
+            ));
+
+            // the first frame has nothing to go back to, previous clears it like background
+            match control.dispose_op {
+                DisposeOp::None => {}
+                DisposeOp::Previous if i > 0 => {
+                    for (&xy, pixel) in region.iter().zip(previous) {
+                        canvas[at(xy)] = pixel;
+                    }
+                }
+                DisposeOp::Background | DisposeOp::Previous => {
+                    for &xy in &region {
+                        canvas[at(xy)] = [0; 4];
+                    }
+                }
+            }
+        }
+        Ok(composed)
+    }
+
+    /// the time for one play of all frames in seconds
+    pub fn duration(&self) -> f64 {
+        self.frames.iter().map(|frame| frame.control.delay()).sum()
+    }
+}
+
+/// alpha composite `source` over `target`, both RGBA with 16 bit samples
+fn over(source: [u16; 4], target: [u16; 4]) -> [u16; 4] {
+    let source_alpha = source[3] as f64 / 65535.0;
+    let target_alpha = target[3] as f64 / 65535.0 * (1.0 - source_alpha);
+    let alpha = source_alpha + target_alpha;
+    if alpha == 0.0 {
+        return [0; 4];
+    }
+    let mut out = [0u16; 4];
+    for c in 0..3 {
+        let value = (source[c] as f64 * source_alpha + target[c] as f64 * target_alpha) / alpha;
+        out[c] = value.round() as u16;
+    }
+    out[3] = (alpha * 65535.0).round() as u16;
+    out
+}
+
+/// a still png of the image
+pub fn still_png(image: &ImageData) -> Result<Png> {
+    Ok(Png::from_chunks(vec![
+        Chunk::new("IHDR".parse().unwrap(), image.header.to_bytes()),
+        Chunk::new("IDAT".parse().unwrap(), image.encode()?),
+        Chunk::new("IEND".parse().unwrap(), Vec::new()),
+    ]))
+}
+
+/// fdAT chunks for the zlib stream of a frame, numbered from `sequence`
+fn fdat_chunks<'a>(stream: impl IntoIterator<Item = &'a [u8]>, sequence: &mut u32) -> Vec<Chunk> {
+    stream
+        .into_iter()
+        .map(|data| {
+            let chunk = Chunk::new(
+                "fdAT".parse().unwrap(),
+                [sequence.to_be_bytes().as_ref(), data].concat(),
+            );
+            *sequence += 1;
+            chunk
+        })
+        .collect()
+}
+
+/// the smallest region `(x, y, width, height)` holding every pixel that differs, 1x1 at the
+/// origin when none does as a frame can not be empty
+fn changed_region(header: &Header, previous: &[u16], current: &[u16]) -> (u32, u32, u32, u32) {
+    let channels = header.channels();
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for y in 0..header.height {
+        for x in 0..header.width {
+            let at = (y * header.width + x) as usize * channels;
+            if previous[at..at + channels] != current[at..at + channels] {
+                let (x0, y0, x1, y1) = bounds.unwrap_or((x, y, x, y));
+                bounds = Some((x0.min(x), y0.min(y), x1.max(x), y1.max(y)));
+            }
+        }
+    }
+    bounds.map_or((0, 0, 1, 1), |(x0, y0, x1, y1)| {
+        (x0, y0, x1 - x0 + 1, y1 - y0 + 1)
+    })
+}
+
+/// an animation of the still pngs, the first one being the default image. they must share
+/// IHDR, PLTE and tRNS. every frame is shown for `delay_ms` and the animation plays `loops`
+/// times, 0 for forever. with `crop` the frames after the first only hold the region that
+/// changed since the one before.
+pub fn build(frames: &[Png], delay_ms: u16, loops: u32, crop: bool) -> Result<Png> {
+    let first = frames
+        .first()
+        .ok_or_else(|| Error::from("no frames to build an animation from"))?;
+    let header = Header::from_png(first)?;
+    let palette = first.palette()?;
+    let transparency = first.transparency()?;
+    for (i, frame) in frames.iter().enumerate() {
+        if ANIMATION_CHUNK_TYPES
+            .iter()
+            .any(|chunk_type| frame.chunk_by_type(chunk_type).is_some())
+        {
+            return Err(Error::from(format!("frame {} is already animated", i)));
+        }
+        let frame_header = Header::from_png(frame)?;
+        if frame_header != header {
+            return Err(Error::from(format!(
+                "frame {} is {}x{} of color type {} at {} bits, frame 0 is {}x{} of color type {} at {} bits",
+                i,
+                frame_header.width,
+                frame_header.height,
+                frame_header.color_type.code(),
+                frame_header.bit_depth,
+                header.width,
+                header.height,
+                header.color_type.code(),
+                header.bit_depth
+            )));
+        }
+        if frame.palette()? != palette || frame.transparency()? != transparency {
+            return Err(Error::from(format!(
+                "frame {} has another PLTE or tRNS than frame 0",
+                i
+            )));
+        }
+    }
+
+    let frame_control = |sequence_number: u32, (x_offset, y_offset, width, height)| FrameControl {
+        sequence_number,
+        width,
+        height,
+        x_offset,
+        y_offset,
+        delay_num: delay_ms,
+        delay_den: 1000,
+        dispose_op: DisposeOp::None,
+        blend_op: BlendOp::Source,
+    };
+    let full = (0, 0, header.width, header.height);
+    let control = AnimationControl {
+        num_frames: u32::try_from(frames.len())
+            .map_err(|_| Error::from("too many frames for acTL"))?,
+        num_plays: loops,
+    };
+    let mut png = Png::try_from(first.as_bytes().as_slice())?;
+    png.set_chunk_before(
+        Chunk::new("acTL".parse().unwrap(), control.to_bytes()),
+        &["IDAT"],
+    );
+    png.set_chunk_before(
+        Chunk::new("fcTL".parse().unwrap(), frame_control(0, full).to_bytes()),
+        &["IDAT"],
+    );
+
+    let mut sequence = 1;
+    let mut previous = if crop {
+        Some(ImageData::decode(first)?.samples())
+    } else {
+        None
+    };
+    for frame in &frames[1..] {
+        let idat = frame.chunks_by_type("IDAT");
+        let (region, chunks) = match previous.as_mut() {
+            None => {
+                let fctl = frame_control(sequence, full);
+                sequence += 1;
+                let chunks = fdat_chunks(idat.iter().map(|chunk| chunk.data()), &mut sequence);
+                (fctl, chunks)
+            }
+            Some(previous) => {
+                let samples = ImageData::decode(frame)?.samples();
+                let (x, y, width, height) = changed_region(&header, previous, &samples);
+                let channels = header.channels();
+                let cropped: Vec<u16> = (y..y + height)
+                    .flat_map(|row| {
+                        let start = (row * header.width + x) as usize * channels;
+                        samples[start..start + width as usize * channels].to_vec()
+                    })
+                    .collect();
+                let sub_header = Header {
+                    width,
+                    height,
+                    ..header
+                };
+                let stream = ImageData::from_samples(sub_header, &cropped, None).encode()?;
+                let max_len = idat
+                    .iter()
+                    .map(|chunk| chunk.length())
+                    .max()
+                    .unwrap_or_default()
+                    .max(8192);
+                let fctl = frame_control(sequence, (x, y, width, height));
+                sequence += 1;
+                *previous = samples;
+                (fctl, fdat_chunks(stream.chunks(max_len), &mut sequence))
+            }
+        };
+        png.insert_before_iend(Chunk::new("fcTL".parse().unwrap(), region.to_bytes()));
+        for chunk in chunks {
+            png.insert_before_iend(chunk);
+        }
+    }
+    // the result must read back as the animation it was meant to be
+    Animation::from_png(&png)?;
+    Ok(png)
+}
+
+/// one line for the animation then one for each frame with its start time
+impl fmt::Display for Animation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plays = match self.control.num_plays {
+            0 => String::from("loops forever"),
+            1 => String::from("plays once"),
+            plays => format!("plays {} times", plays),
+        };
+        writeln!(
+            f,
+            "{} frames, {}, {:.3}s per play",
+            self.frames.len(),
+            plays,
+            self.duration()
+        )?;
+        let mut start = 0.0;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let control = &frame.control;
+            writeln!(
+                f,
+                "frame {} at {:.3}s: {}x{} at ({}, {}) for {:.3}s, dispose {}, blend {}, {} {} ({} bytes)",
+                i,
+                start,
+                control.width,
+                control.height,
+                control.x_offset,
+                control.y_offset,
+                control.delay(),
+                control.dispose_op,
+                control.blend_op,
+                frame.data_chunks,
+                if frame.is_default_image { "IDAT" } else { "fdAT" },
+                frame.data.len()
+            )?;
+            start += control.delay();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, image::ColorType};
+
+    type Chunks = Vec<(&'static str, Vec<u8>)>;
+
+    fn frame_control(sequence_number: u32, width: u32, height: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    fn fdat(sequence_number: u32, data: &[u8]) -> Vec<u8> {
+        [sequence_number.to_be_bytes().as_ref(), data].concat()
+    }
+
+    /// a 4x4 animation of 3 frames, the default image being the first
+    fn animated_png(chunks: Chunks) -> Png {
+        let header = Header {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let chunks = [("IHDR", header.to_bytes())]
+            .into_iter()
+            .chain(chunks)
+            .chain([("IEND", Vec::new())])
+            .map(|(chunk_type, data)| Chunk::new(chunk_type.parse().unwrap(), data))
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    fn testing_chunks() -> Chunks {
+        let mut second = frame_control(1, 2, 2);
+        second.x_offset = 2;
+        second.dispose_op = DisposeOp::Previous;
+        second.blend_op = BlendOp::Over;
+        vec![
+            (
+                "acTL",
+                AnimationControl {
+                    num_frames: 3,
+                    num_plays: 0,
+                }
+                .to_bytes(),
+            ),
+            ("fcTL", frame_control(0, 4, 4).to_bytes()),
+            ("IDAT", vec![1, 2, 3]),
+            ("IDAT", vec![4]),
+            ("fcTL", second.to_bytes()),
+            ("fdAT", fdat(2, &[5, 6])),
+            ("fcTL", frame_control(3, 4, 4).to_bytes()),
+            ("fdAT", fdat(4, &[7])),
+            ("fdAT", fdat(5, &[8])),
+        ]
+    }
+
+    #[test]
+    fn test_frame_control() {
+        let control = frame_control(7, 3, 2);
+        assert_eq!(FrameControl::parse(&control.to_bytes()).unwrap(), control);
+        assert_eq!(control.delay(), 0.1);
+        let mut data = control.to_bytes();
+        data[24] = 3;
+        assert!(FrameControl::parse(&data).is_err());
+        assert!(FrameControl::parse(&data[..25]).is_err());
+        assert!(AnimationControl::parse(&[0; 8]).is_err());
+    }
+
+    #[test]
+    fn test_animation() {
+        let animation = Animation::from_png(&animated_png(testing_chunks()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(animation.frames.len(), 3);
+        assert!(animation.frames[0].is_default_image);
+        assert_eq!(animation.frames[0].data, [1, 2, 3, 4]);
+        assert_eq!(animation.frames[1].data, [5, 6]);
+        assert_eq!(animation.frames[2].data, [7, 8]);
+        assert!((animation.duration() - 0.3).abs() < 1e-9);
+        let timeline = animation.to_string();
+        assert!(timeline.starts_with("3 frames, loops forever, 0.300s per play\n"));
+        assert!(timeline.contains(
+            "frame 1 at 0.100s: 2x2 at (2, 0) for 0.100s, dispose previous, blend over, 1 fdAT (2 bytes)"
+        ));
+
+        assert_eq!(
+            Animation::from_png(&animated_png(vec![("IDAT", vec![0])])).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_default_image_outside_animation() {
+        let chunks = vec![
+            (
+                "acTL",
+                AnimationControl {
+                    num_frames: 1,
+                    num_plays: 2,
+                }
+                .to_bytes(),
+            ),
+            ("IDAT", vec![1]),
+            ("fcTL", frame_control(0, 1, 1).to_bytes()),
+            ("fdAT", fdat(1, &[2])),
+        ];
+        let animation = Animation::from_png(&animated_png(chunks)).unwrap().unwrap();
+        assert!(!animation.frames[0].is_default_image);
+        assert_eq!(animation.frames[0].data, [2]);
+    }
+
+    #[test]
+    fn test_invalid_animation() {
+        let invalid = |edit: &dyn Fn(&mut Chunks)| {
+            let mut chunks = testing_chunks();
+            edit(&mut chunks);
+            Animation::from_png(&animated_png(chunks)).is_err()
+        };
+        // sequence numbers out of order
+        assert!(invalid(&|chunks| chunks.swap(7, 8)));
+        // a gap in the sequence numbers
+        assert!(invalid(&|chunks| chunks[5].1 = fdat(3, &[5, 6])));
+        // frame count does not match acTL
+        assert!(invalid(&|chunks| {
+            chunks.truncate(6);
+        }));
+        // region outside the image
+        assert!(invalid(&|chunks| {
+            let mut control = frame_control(1, 2, 2);
+            control.x_offset = 3;
+            chunks[4].1 = control.to_bytes();
+        }));
+        // default image frame smaller than IHDR
+        assert!(invalid(
+            &|chunks| chunks[1].1 = frame_control(0, 2, 2).to_bytes()
+        ));
+        // fdAT data for the default image frame
+        assert!(invalid(&|chunks| {
+            chunks.insert(4, ("fdAT", fdat(1, &[0])));
+        }));
+        // acTL after IDAT
+        assert!(invalid(&|chunks| {
+            let actl = chunks.remove(0);
+            chunks.insert(3, actl);
+        }));
+        // fcTL without acTL
+        assert!(invalid(&|chunks| {
+            chunks.remove(0);
+        }));
+    }
+
+    #[test]
+    fn test_over() {
+        assert_eq!(
+            over([65535, 0, 0, 65535], [0, 65535, 0, 65535]),
+            [65535, 0, 0, 65535]
+        );
+        assert_eq!(over([0, 0, 0, 0], [1, 2, 3, 65535]), [1, 2, 3, 65535]);
+        let half = over([65535, 0, 0, 32768], [0, 0, 65535, 65535]);
+        assert_eq!(half[3], 65535);
+        assert!((half[0] as i32 - 32768).abs() <= 1 && (half[2] as i32 - 32767).abs() <= 1);
+        assert_eq!(over([0; 4], [0; 4]), [0; 4]);
+    }
+
+    #[test]
+    fn test_compose() {
+        let solid = |width: u32, height: u32, rgba: [u8; 4]| {
+            let pixels = vec![rgba.map(|v| v as u16 * 257); (width * height) as usize];
+            ImageData::from_rgba(width, height, &pixels, false)
+                .encode()
+                .unwrap()
+        };
+        let mut second = frame_control(1, 2, 2);
+        second.x_offset = 2;
+        second.blend_op = BlendOp::Over;
+        second.dispose_op = DisposeOp::Previous;
+        let mut third = frame_control(3, 2, 2);
+        third.dispose_op = DisposeOp::Background;
+        let mut fourth = frame_control(5, 1, 1);
+        fourth.blend_op = BlendOp::Over;
+        let png = animated_png(vec![
+            (
+                "acTL",
+                AnimationControl {
+                    num_frames: 4,
+                    num_plays: 0,
+                }
+                .to_bytes(),
+            ),
+            ("fcTL", frame_control(0, 4, 4).to_bytes()),
+            ("IDAT", solid(4, 4, [255, 0, 0, 255])),
+            ("fcTL", second.to_bytes()),
+            ("fdAT", fdat(2, &solid(2, 2, [0, 0, 255, 0]))),
+            ("fcTL", third.to_bytes()),
+            ("fdAT", fdat(4, &solid(2, 2, [0, 255, 0, 255]))),
+            ("fcTL", fourth.to_bytes()),
+            ("fdAT", fdat(6, &solid(1, 1, [0, 0, 255, 255]))),
+        ]);
+        let frames = Animation::from_png(&png)
+            .unwrap()
+            .unwrap()
+            .compose(&png)
+            .unwrap();
+        assert_eq!(frames.len(), 4);
+        let pixel = |frame: usize, x: usize, y: usize| -> [u8; 4] {
+            frames[frame].passes[0].data[(y * 4 + x) * 4..(y * 4 + x) * 4 + 4]
+                .try_into()
+                .unwrap()
+        };
+        assert_eq!(pixel(0, 3, 3), [255, 0, 0, 255]);
+        // a transparent frame blended over leaves the canvas as it was
+        assert_eq!(pixel(1, 2, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(2, 0, 0), [0, 255, 0, 255]);
+        // the third frame was cleared to transparent black, only the fourth pixel is drawn
+        assert_eq!(pixel(3, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(3, 1, 1), [0, 0, 0, 0]);
+        assert_eq!(pixel(3, 3, 3), [255, 0, 0, 255]);
+        assert_eq!(frames[3].header.color_type, ColorType::Rgba);
+
+        let still = still_png(&frames[3]).unwrap();
+        assert_eq!(ImageData::decode(&still).unwrap(), frames[3]);
+    }
+
+    #[test]
+    fn test_compose_forged_size() {
+        for size in [65535, 2147483647] {
+            let mut png = animated_png(vec![
+                (
+                    "acTL",
+                    AnimationControl {
+                        num_frames: 1,
+                        num_plays: 0,
+                    }
+                    .to_bytes(),
+                ),
+                ("fcTL", frame_control(0, size, size).to_bytes()),
+                (
+                    "IDAT",
+                    ImageData::from_rgba(1, 1, &[[0; 4]], false)
+                        .encode()
+                        .unwrap(),
+                ),
+            ]);
+            let header = Header {
+                width: size,
+                height: size,
+                bit_depth: 8,
+                color_type: ColorType::Rgba,
+                interlaced: false,
+            };
+            png.set_chunk_before(Chunk::new("IHDR".parse().unwrap(), header.to_bytes()), &[]);
+            let animation = Animation::from_png(&png).unwrap().unwrap();
+            assert!(animation.compose(&png).is_err());
+        }
+    }
+
+    /// a still 4x4 RGBA png of one color with the top left pixel replaced by `corner`
+    fn still(color: [u8; 4], corner: [u8; 4]) -> Png {
+        let mut pixels = vec![color.map(|v| v as u16 * 257); 16];
+        pixels[0] = corner.map(|v| v as u16 * 257);
+        still_png(&ImageData::from_rgba(4, 4, &pixels, false)).unwrap()
+    }
+
+    #[test]
+    fn test_build() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let frames = [
+            still(red, red),
+            still(blue, blue),
+            still(blue, red),
+            still(blue, red),
+        ];
+        for crop in [false, true] {
+            let png = build(&frames, 40, 3, crop).unwrap();
+            let animation = Animation::from_png(&png).unwrap().unwrap();
+            assert_eq!(animation.control.num_plays, 3);
+            assert_eq!(animation.frames.len(), 4);
+            assert!(animation.frames[0].is_default_image);
+            assert!((animation.duration() - 0.16).abs() < 1e-9);
+
+            let regions: Vec<(u32, u32, u32, u32)> = animation
+                .frames
+                .iter()
+                .map(|frame| {
+                    let c = frame.control;
+                    (c.x_offset, c.y_offset, c.width, c.height)
+                })
+                .collect();
+            if crop {
+                // the whole image changed, then only the corner, then nothing
+                assert_eq!(
+                    regions,
+                    [(0, 0, 4, 4), (0, 0, 4, 4), (0, 0, 1, 1), (0, 0, 1, 1)]
+                );
+            } else {
+                assert!(regions.iter().all(|region| *region == (0, 0, 4, 4)));
+            }
+            let composed = animation.compose(&png).unwrap();
+            for (frame, image) in frames.iter().zip(composed) {
+                assert_eq!(image, ImageData::decode(frame).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_mismatch() {
+        let small = still_png(&ImageData::from_rgba(2, 2, &[[0; 4]; 4], false)).unwrap();
+        assert!(build(&[still([0; 4], [0; 4]), small], 100, 0, false).is_err());
+        assert!(build(&[], 100, 0, false).is_err());
+        let animated = build(&[still([0; 4], [0; 4])], 100, 0, false).unwrap();
+        assert!(build(&[animated], 100, 0, false).is_err());
+    }
+}
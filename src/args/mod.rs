@@ -1,4 +1,11 @@
 use std::path::PathBuf;
+
+use crate::{
+    color::{Chromaticities, Cicp, Gamma, RenderingIntent},
+    compression::Compression,
+    metadata::Time,
+    stego::Method,
+};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -11,6 +18,32 @@ pub enum Commands {
     Remove(RemoveArgs),
     /// Print the chunk info from the file
     Print(PrintArgs),
+    /// Generate a recipient key pair, the identity is written to the file path
+    Keygen(KeygenArgs),
+    /// Sign the critical chunks and the chosen ancillary chunks
+    Sign(SignArgs),
+    /// Verify the signature and report modified chunks
+    Verify(VerifyArgs),
+    /// Report how many bytes each storage method can hide in the file
+    Capacity(CapacityArgs),
+    /// Look for signs of hidden data and score how suspicious each one is
+    Analyze(AnalyzeArgs),
+    /// Show the color space chunks, or set and strip them
+    Color(ColorArgs),
+    /// Show or set the physical pixel density of pHYs
+    Dpi(DpiCommands),
+    /// List the EXIF tags of eXIf, or remove them
+    Exif(ExifCommands),
+    /// Show or set the last modification time of tIME
+    Time(TimeCommands),
+    /// Work with the frames of an animated png
+    Apng(ApngCommands),
+    /// List the IDAT chunks, or split or merge them without touching the compressed data
+    Idat(IdatArgs),
+    /// Check the chunks for structural errors, like broken APNG sequence numbers
+    Validate(ValidateArgs),
+    /// Recompress the pixels losslessly, trying smaller color types, filters and deflate levels
+    Optimize(OptimizeArgs),
 }
 
 /// Command line tools to help you hide messages in a png file
@@ -27,18 +60,267 @@ pub struct Opt {
 
 #[derive(Debug, StructOpt)]
 pub struct EncodeArgs {
-    pub message: String,
-    pub chunk_type: String,
+    /// The chunk to hide the message in, not used by the pixel methods
+    pub chunk_type: Option<String>,
+    /// With a second argument the first one is the message, as in `encode <message> <chunk-type>`
+    #[structopt(name = "second-chunk-type", conflicts_with_all = &["message", "file"])]
+    pub second_chunk_type: Option<String>,
+    /// The message to hide
+    #[structopt(
+        short,
+        long,
+        required_unless_one = &["file", "second-chunk-type"],
+        conflicts_with = "file"
+    )]
+    pub message: Option<String>,
+    /// Where to hide the payload: chunk, lsb or palette (indexed images)
+    #[structopt(long, default_value = "chunk")]
+    pub method: Method,
+    #[structopt(flatten)]
+    pub lsb: LsbArgs,
+    /// Hide the content of this file instead of a message
+    #[structopt(long, parse(from_os_str))]
+    pub file: Option<PathBuf>,
+    /// Encrypt the message with a passphrase (read from PNG_INFO_PASSPHRASE or prompted)
+    #[structopt(long, conflicts_with = "recipients")]
+    pub encrypt: bool,
+    /// Public key file of a recipient allowed to read the message, may be repeated
+    #[structopt(long = "recipient", parse(from_os_str), number_of_values = 1)]
+    pub recipients: Vec<PathBuf>,
+    /// Split payloads larger than this many bytes into numbered chunks of the same type
+    #[structopt(long)]
+    pub split_size: Option<usize>,
+    /// Compress the payload before embedding: deflate, zstd or brotli
+    #[structopt(long)]
+    pub compress: Option<Compression>,
+    /// Update tIME to the current UTC time
+    #[structopt(long)]
+    pub touch: bool,
 }
 
 #[derive(Debug, StructOpt)]
 pub struct DecodeArgs {
-    pub chunk_type: String,
+    /// The chunk holding the message, not used by the pixel methods
+    pub chunk_type: Option<String>,
+    /// Where the payload is hidden: chunk, lsb or palette
+    #[structopt(long, default_value = "chunk")]
+    pub method: Method,
+    #[structopt(flatten)]
+    pub lsb: LsbArgs,
+    /// Identity file used to open a message sealed for recipients
+    #[structopt(long, parse(from_os_str))]
+    pub identity: Option<PathBuf>,
+    /// Write the hidden bytes to this file instead of printing them
+    #[structopt(long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+    /// Refuse to decompress payloads larger than this many bytes (64 MiB by default)
+    #[structopt(long)]
+    pub max_size: Option<usize>,
+}
+/// options of the lsb method, decode must be given the ones encode used
+#[derive(Debug, StructOpt)]
+pub struct LsbArgs {
+    /// Bits written into every selected channel sample
+    #[structopt(long, default_value = "1")]
+    pub bits: u8,
+    /// Channels to write into: letters of `rgba` or `ya` for grayscale, `i` only reads palette images
+    #[structopt(long)]
+    pub channels: Option<String>,
+    /// Allow writing into the alpha channel
+    #[structopt(long)]
+    pub include_alpha: bool,
+    /// Also use fully transparent pixels
+    #[structopt(long)]
+    pub include_transparent: bool,
+    /// Scatter the payload over the pixels in an order derived from the passphrase
+    #[structopt(long)]
+    pub scatter: bool,
 }
+
 #[derive(Debug, StructOpt)]
 pub struct RemoveArgs {
-    pub chunk_type: String,
+    #[structopt(required_unless = "animation")]
+    pub chunk_type: Option<String>,
+    /// Remove acTL, fcTL and fdAT, keeping the default image as a still png
+    #[structopt(long, conflicts_with = "chunk-type")]
+    pub animation: bool,
+    /// Update tIME to the current UTC time
+    #[structopt(long)]
+    pub touch: bool,
 }
 
 #[derive(Debug, StructOpt)]
 pub struct PrintArgs {}
+
+#[derive(Debug, StructOpt)]
+pub struct AnalyzeArgs {}
+
+#[derive(Debug, StructOpt)]
+pub struct ValidateArgs {}
+
+#[derive(Debug, StructOpt)]
+pub struct IdatArgs {
+    /// Split the image data into IDAT chunks of at most this many bytes
+    #[structopt(long, conflicts_with = "merge")]
+    pub split: Option<usize>,
+    /// Join the IDAT chunks into one
+    #[structopt(long)]
+    pub merge: bool,
+    /// Update tIME to the current UTC time
+    #[structopt(long)]
+    pub touch: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct OptimizeArgs {
+    /// Ancillary chunk type to keep, repeatable, or "all". Color, HDR and pHYs chunks when omitted
+    #[structopt(long)]
+    pub keep: Vec<String>,
+    /// Write the result here instead of over the input
+    #[structopt(long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+    /// Update tIME to the current UTC time
+    #[structopt(long)]
+    pub touch: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct KeygenArgs {
+    /// Generate an Ed25519 signing key pair instead of a recipient key pair
+    #[structopt(long)]
+    pub signing: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SignArgs {
+    /// Signing key file written by `keygen --signing`
+    #[structopt(long, parse(from_os_str))]
+    pub key: PathBuf,
+    /// Ancillary chunk type to cover as well, may be repeated
+    #[structopt(long = "include", number_of_values = 1)]
+    pub include: Vec<String>,
+    /// Update tIME to the current UTC time before signing
+    #[structopt(long)]
+    pub touch: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyArgs {
+    /// Trusted verifying key file, the signer must match it
+    #[structopt(long, parse(from_os_str))]
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CapacityArgs {
+    /// Also report the capacity when payloads are split into chunks of this many bytes
+    #[structopt(long)]
+    pub split_size: Option<usize>,
+    /// Channels counted by the lsb method: letters of `rgba` or `ya`
+    #[structopt(long)]
+    pub channels: Option<String>,
+    /// Count the alpha channel for the lsb method
+    #[structopt(long)]
+    pub include_alpha: bool,
+    /// Count fully transparent pixels for the lsb method
+    #[structopt(long)]
+    pub include_transparent: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ColorArgs {
+    /// Set gAMA to the image gamma, like 0.45455 or 1/2.2
+    #[structopt(long)]
+    pub gamma: Option<Gamma>,
+    /// Set cHRM: white x,y red x,y green x,y blue x,y
+    #[structopt(long)]
+    pub chrm: Option<Chromaticities>,
+    /// Set sRGB with the rendering intent: perceptual, relative-colorimetric, saturation or absolute-colorimetric
+    #[structopt(long)]
+    pub srgb: Option<RenderingIntent>,
+    /// Set iCCP to the ICC profile in this file
+    #[structopt(long, parse(from_os_str))]
+    pub icc: Option<PathBuf>,
+    /// Name of the profile set with --icc, the file name by default
+    #[structopt(long)]
+    pub icc_name: Option<String>,
+    /// Set cICP: primaries,transfer,matrix,full-range, like 9,16,0,1 for BT.2100 PQ
+    #[structopt(long)]
+    pub cicp: Option<Cicp>,
+    /// Remove a color chunk (gAMA, cHRM, sRGB, iCCP, cICP, mDCv, cLLi or all), may be repeated
+    #[structopt(long = "strip", number_of_values = 1)]
+    pub strip: Vec<String>,
+    /// Write the uncompressed ICC profile of iCCP to this file
+    #[structopt(long, parse(from_os_str))]
+    pub extract_icc: Option<PathBuf>,
+    /// Update tIME to the current UTC time when a chunk is set or stripped
+    #[structopt(long)]
+    pub touch: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum DpiCommands {
+    /// Show the pixel density
+    Show,
+    /// Set the pixel density in dots per inch, the vertical one defaults to the horizontal
+    Set {
+        x: f64,
+        y: Option<f64>,
+        /// Update tIME to the current UTC time
+        #[structopt(long)]
+        touch: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ExifCommands {
+    /// List every tag with the directory it is in
+    List,
+    /// Remove the GPS tags and keep the others
+    RemoveGps {
+        /// Update tIME to the current UTC time
+        #[structopt(long)]
+        touch: bool,
+    },
+    /// Remove the eXIf chunk
+    Remove {
+        /// Update tIME to the current UTC time
+        #[structopt(long)]
+        touch: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum TimeCommands {
+    /// Show the last modification time
+    Show,
+    /// Set the time from RFC 3339, like 2024-01-02T03:04:05Z, other offsets are converted to UTC
+    Set { time: Time },
+    /// Set the time to now
+    Touch,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ApngCommands {
+    /// Compose every frame onto the canvas and write each one as a png
+    Extract {
+        /// Directory for frame_000.png, frame_001.png, ..., created when missing
+        #[structopt(long, parse(from_os_str))]
+        out_dir: PathBuf,
+    },
+    /// Build an animation from still pngs of the same size and color type, written to the file path
+    Build {
+        /// The frames in display order, the first one is the image shown without animation support
+        #[structopt(parse(from_os_str), required = true)]
+        frames: Vec<PathBuf>,
+        /// How long each frame is shown in milliseconds
+        #[structopt(long, default_value = "100")]
+        delay: u16,
+        /// How many times the animation plays, 0 for forever
+        #[structopt(long, default_value = "0")]
+        loops: u32,
+        /// Only store the region that changed since the previous frame
+        #[structopt(long)]
+        crop: bool,
+    },
+}
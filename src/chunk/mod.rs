@@ -1,13 +1,10 @@
 /// pay attention to the crc crate.
 /// it has been changed a lot.
-use crc::{Algorithm, Crc, CRC_32_ISO_HDLC};
+use crc::{Crc, CRC_32_ISO_HDLC};
 
-use crate::{
-    chunk_type::{self, ChunkType},
-    Error,
-};
+use crate::{chunk_type::ChunkType, Error};
 
-use std::fmt::{self};
+use std::fmt;
 
 #[derive(Debug)]
 pub struct Chunk {
@@ -18,10 +15,13 @@ pub struct Chunk {
 }
 
 impl Chunk {
+    /// the largest chunk data length allowed by the spec, 2^31 - 1
+    pub const MAX_LENGTH: usize = (1 << 31) - 1;
+
     pub fn new(chunk_type: ChunkType, chunk_data: Vec<u8>) -> Chunk {
         let calc_hash = [&chunk_type.bytes(), chunk_data.as_slice()].concat();
-        let CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-        let crc_res = CASTAGNOLI.checksum(&calc_hash);
+        let castagnoli: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+        let crc_res = castagnoli.checksum(&calc_hash);
         Chunk {
             length: chunk_data.len() as u32,
             chunk_type,
@@ -30,11 +30,11 @@ impl Chunk {
         }
     }
 
-    fn length(&self) -> usize {
+    pub fn length(&self) -> usize {
         self.length as usize
     }
 
-    fn data(&self) -> &[u8] {
+    pub fn data(&self) -> &[u8] {
         &self.chunk_data
     }
 
@@ -42,7 +42,7 @@ impl Chunk {
         &self.chunk_type
     }
 
-    fn crc(&self) -> u32 {
+    pub fn crc(&self) -> u32 {
         self.crc
     }
 
@@ -62,41 +62,43 @@ impl Chunk {
     }
 }
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = &'static str;
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+impl Chunk {
+    /// the chunk in `value` and the CRC stored after it, which is not checked
+    pub fn parse(value: &[u8]) -> Result<(Chunk, u32), Error> {
+        if value.len() < 12 {
+            return Err(Error::from("a chunk needs at least 12 bytes"));
+        }
         let length = u32::from_be_bytes(value[0..4].try_into().unwrap()) as usize;
+        if length > Chunk::MAX_LENGTH {
+            return Err(Error::from("chunk length is over 2^31 - 1"));
+        }
         if value.len() != length + 3 * 4 {
-            return Err("chunk_data does not have enough bytes.");
+            return Err(Error::from("chunk_data does not have enough bytes."));
         }
         let data: [u8; 4] = value[4..8].try_into().unwrap();
-        let chunk_type: ChunkType = data.try_into().unwrap();
-        let chunk_data = value[8..8 + length].to_vec();
-
-        let hash_data = [&chunk_type.bytes(), chunk_data.as_slice()].concat();
-        let CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-        let crc_res = CASTAGNOLI.checksum(&hash_data);
+        let chunk_type = ChunkType::try_from(data).map_err(|e| Error::from(e.to_string()))?;
+        let chunk = Chunk::new(chunk_type, value[8..8 + length].to_vec());
+        let stored = u32::from_be_bytes(value[8 + length..].try_into().unwrap());
+        Ok((chunk, stored))
+    }
+}
 
-        let used_offset = 8 + length;
-        let tmp: [u8; 4] = value[used_offset..(used_offset + 4)].try_into().unwrap();
-        if crc_res != u32::from_be_bytes(tmp) {
-            return Err("crc check error");
+impl TryFrom<&[u8]> for Chunk {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let (chunk, stored) = Chunk::parse(value)?;
+        if chunk.crc() != stored {
+            return Err(Error::from("crc check error"));
         }
-        Ok(Chunk {
-            length: length as u32,
-            chunk_type,
-            chunk_data,
-            crc: crc_res,
-        })
+        Ok(chunk)
     }
 }
 
 impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "type :{}", self.chunk_type());
-        writeln!(f, "data size :{}", self.chunk_data.len());
-        writeln!(f, "crc :{}", self.crc());
-        Ok(())
+        writeln!(f, "type :{}", self.chunk_type())?;
+        writeln!(f, "data size :{}", self.chunk_data.len())?;
+        writeln!(f, "crc :{}", self.crc())
     }
 }
 
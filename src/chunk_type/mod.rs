@@ -1,7 +1,7 @@
 use std::{fmt::Display, str::FromStr};
 
 /// [introduction](http://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html#Chunk-naming-conventions)
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChunkType {
     bytes: [u8; 4],
 }
@@ -9,7 +9,7 @@ pub struct ChunkType {
 #[derive(Debug)]
 pub enum ChunkTypeError {
     InvalidLength,
-    invalidByte(u8),
+    InvalidByte(u8),
 }
 
 impl Display for ChunkTypeError {
@@ -18,7 +18,7 @@ impl Display for ChunkTypeError {
             ChunkTypeError::InvalidLength => {
                 write!(f, "Chunk types must be 4 bytes long!")
             }
-            ChunkTypeError::invalidByte(b) => {
+            ChunkTypeError::InvalidByte(b) => {
                 write!(f, "invalid bit: {}", b)
             }
         }
@@ -30,30 +30,24 @@ impl ChunkType {
         self.bytes
     }
 
-    fn is_critical(&self) -> bool {
+    pub fn is_critical(&self) -> bool {
         (self.bytes[0] & 0x20) != 0x20
     }
-    fn is_public(&self) -> bool {
+    pub fn is_public(&self) -> bool {
         (self.bytes[1] & 0x20) != 0x20
     }
 
-    fn is_reserved_bit_valid(&self) -> bool {
+    pub fn is_reserved_bit_valid(&self) -> bool {
         (self.bytes[2] & 0x20) != 0x20
     }
-    fn is_safe_to_copy(&self) -> bool {
+    pub fn is_safe_to_copy(&self) -> bool {
         (self.bytes[3] & 0x20) == 0x20
     }
-    fn is_valid(&self) -> bool {
-        for i in self.bytes.as_ref().iter() {
-            if !((*i >= 'a' as u8 && *i <= 'z' as u8) || (*i >= 'A' as u8 && *i <= 'Z' as u8)) {
-                return false;
-            }
-        }
-        if self.bytes[2] >= 'A' as u8 && self.bytes[2] <= 'Z' as u8 {
-            true
-        } else {
-            false
+    pub fn is_valid(&self) -> bool {
+        if !self.bytes.iter().all(|b| b.is_ascii_alphabetic()) {
+            return false;
         }
+        self.bytes[2].is_ascii_uppercase()
     }
 }
 
@@ -62,8 +56,8 @@ impl TryFrom<[u8; 4]> for ChunkType {
     type Error = ChunkTypeError;
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
         for i in value {
-            if !((i >= 'a' as u8 && i <= 'z' as u8) || (i >= 'A' as u8 && i <= 'Z' as u8)) {
-                return Err(ChunkTypeError::invalidByte(i));
+            if !i.is_ascii_alphabetic() {
+                return Err(ChunkTypeError::InvalidByte(i));
             }
         }
         Ok(ChunkType { bytes: value })
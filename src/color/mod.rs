@@ -0,0 +1,884 @@
+/// the color space chunks: gAMA, cHRM, sRGB, iCCP and cICP, and the HDR chunks mDCv and cLLi.
+/// decoders use the first of cICP, iCCP, sRGB and gAMA/cHRM they support, so only the
+/// one with the highest precedence matters and the others should agree with it.
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
+use std::{
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use crate::{chunk::Chunk, compression::DEFAULT_MAX_SIZE, png::Png, Error, Result};
+
+/// chunk types handled here, in the order of precedence
+pub const COLOR_CHUNK_TYPES: [&str; 5] = ["cICP", "iCCP", "sRGB", "cHRM", "gAMA"];
+
+/// color chunks must come before these
+pub const COLOR_CHUNKS_BEFORE: [&str; 2] = ["PLTE", "IDAT"];
+
+/// gAMA and cHRM store their values times 100000
+const SCALE: f64 = 100_000.0;
+
+/// gAMA of sRGB, 1 / 2.2
+pub const SRGB_GAMMA: u32 = 45455;
+
+/// cHRM of sRGB: white, red, green and blue
+pub const SRGB_CHROMATICITIES: Chromaticities = Chromaticities {
+    white: (31270, 32900),
+    red: (64000, 33000),
+    green: (30000, 60000),
+    blue: (15000, 6000),
+};
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn check_len(chunk_type: &str, data: &[u8], len: usize) -> Result<()> {
+    if data.len() != len {
+        return Err(Error::from(format!(
+            "{} must be {} bytes, found {}",
+            chunk_type,
+            len,
+            data.len()
+        )));
+    }
+    Ok(())
+}
+
+/// gAMA: the image gamma times 100000, 45455 for 1 / 2.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gamma(pub u32);
+
+impl Gamma {
+    pub fn parse(data: &[u8]) -> Result<Gamma> {
+        check_len("gAMA", data, 4)?;
+        match be_u32(data, 0) {
+            0 => Err(Error::from("gAMA must not be zero")),
+            gamma => Ok(Gamma(gamma)),
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    pub fn value(self) -> f64 {
+        self.0 as f64 / SCALE
+    }
+}
+
+impl FromStr for Gamma {
+    type Err = Error;
+    /// the image gamma like `0.45455`, or the display exponent as `1/2.2`
+    fn from_str(s: &str) -> Result<Gamma> {
+        let value = match s.split_once('/') {
+            Some((numerator, denominator)) => {
+                numerator.trim().parse::<f64>()? / denominator.trim().parse::<f64>()?
+            }
+            None => s.trim().parse::<f64>()?,
+        };
+        let scaled = (value * SCALE).round();
+        if !(1.0..=u32::MAX as f64).contains(&scaled) {
+            return Err(Error::from(format!("gamma `{}` is out of range", s)));
+        }
+        Ok(Gamma(scaled as u32))
+    }
+}
+
+impl fmt::Display for Gamma {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.5} (1/{:.2})", self.value(), 1.0 / self.value())
+    }
+}
+
+/// cHRM: x and y of the white point and the primaries, times 100000
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chromaticities {
+    pub white: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+impl Chromaticities {
+    pub fn parse(data: &[u8]) -> Result<Chromaticities> {
+        check_len("cHRM", data, 32)?;
+        let point = |i: usize| (be_u32(data, i * 8), be_u32(data, i * 8 + 4));
+        Ok(Chromaticities {
+            white: point(0),
+            red: point(1),
+            green: point(2),
+            blue: point(3),
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [self.white, self.red, self.green, self.blue]
+            .iter()
+            .flat_map(|(x, y)| [x.to_be_bytes(), y.to_be_bytes()].concat())
+            .collect()
+    }
+}
+
+impl FromStr for Chromaticities {
+    type Err = Error;
+    /// eight comma separated values: white x, y, red x, y, green x, y and blue x, y
+    fn from_str(s: &str) -> Result<Chromaticities> {
+        let values = s
+            .split(',')
+            .map(|value| {
+                let scaled = (value.trim().parse::<f64>()? * SCALE).round();
+                if !(0.0..=u32::MAX as f64).contains(&scaled) {
+                    return Err(Error::from(format!(
+                        "chromaticity `{}` is out of range",
+                        value
+                    )));
+                }
+                Ok(scaled as u32)
+            })
+            .collect::<Result<Vec<u32>>>()?;
+        if values.len() != 8 {
+            return Err(Error::from(
+                "cHRM needs 8 values: white x,y red x,y green x,y blue x,y",
+            ));
+        }
+        Ok(Chromaticities {
+            white: (values[0], values[1]),
+            red: (values[2], values[3]),
+            green: (values[4], values[5]),
+            blue: (values[6], values[7]),
+        })
+    }
+}
+
+impl fmt::Display for Chromaticities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let point = |(x, y): (u32, u32)| format!("({}, {})", x as f64 / SCALE, y as f64 / SCALE);
+        write!(
+            f,
+            "white {} red {} green {} blue {}",
+            point(self.white),
+            point(self.red),
+            point(self.green),
+            point(self.blue)
+        )
+    }
+}
+
+/// sRGB: the image is sRGB, rendered with this intent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+impl RenderingIntent {
+    const NAMES: [&'static str; 4] = [
+        "perceptual",
+        "relative-colorimetric",
+        "saturation",
+        "absolute-colorimetric",
+    ];
+    const ALL: [RenderingIntent; 4] = [
+        RenderingIntent::Perceptual,
+        RenderingIntent::RelativeColorimetric,
+        RenderingIntent::Saturation,
+        RenderingIntent::AbsoluteColorimetric,
+    ];
+
+    pub fn parse(data: &[u8]) -> Result<RenderingIntent> {
+        check_len("sRGB", data, 1)?;
+        RenderingIntent::ALL
+            .get(data[0] as usize)
+            .copied()
+            .ok_or_else(|| Error::from(format!("unknown sRGB rendering intent {}", data[0])))
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        vec![self as u8]
+    }
+}
+
+impl FromStr for RenderingIntent {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<RenderingIntent> {
+        RenderingIntent::NAMES
+            .iter()
+            .position(|name| *name == s)
+            .map(|i| RenderingIntent::ALL[i])
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "unknown rendering intent `{}`, expected one of {}",
+                    s,
+                    RenderingIntent::NAMES.join(", ")
+                ))
+            })
+    }
+}
+
+impl fmt::Display for RenderingIntent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", RenderingIntent::NAMES[*self as usize])
+    }
+}
+
+/// iCCP: an embedded ICC profile, stored zlib compressed after its name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccProfile {
+    /// 1 to 79 printable Latin-1 characters
+    pub name: String,
+    /// the uncompressed profile
+    pub profile: Vec<u8>,
+}
+
+impl IccProfile {
+    pub fn parse(data: &[u8]) -> Result<IccProfile> {
+        let end = data
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| Error::from("iCCP profile name is not terminated"))?;
+        let name: String = data[..end].iter().map(|byte| *byte as char).collect();
+        check_keyword("iCCP profile name", &name)?;
+        match data.get(end + 1) {
+            Some(0) => {}
+            Some(method) => {
+                return Err(Error::from(format!(
+                    "unknown iCCP compression method {}",
+                    method
+                )))
+            }
+            None => return Err(Error::from("iCCP is truncated")),
+        }
+        let mut profile = Vec::new();
+        ZlibDecoder::new(&data[end + 2..])
+            .take(DEFAULT_MAX_SIZE as u64 + 1)
+            .read_to_end(&mut profile)
+            .map_err(|e| Error::from(format!("iCCP profile inflate failed: {}", e)))?;
+        if profile.len() > DEFAULT_MAX_SIZE {
+            return Err(Error::from("iCCP profile is larger than the limit"));
+        }
+        Ok(IccProfile { name, profile })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        check_keyword("iCCP profile name", &self.name)?;
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&self.profile)?;
+        Ok([
+            self.name.chars().map(|c| c as u8).collect::<Vec<u8>>(),
+            vec![0, 0],
+            encoder.finish()?,
+        ]
+        .concat())
+    }
+}
+
+/// keywords and profile names: 1 to 79 printable Latin-1 characters, no leading,
+/// trailing or double spaces
+pub fn check_keyword(what: &str, keyword: &str) -> Result<()> {
+    let printable = keyword
+        .chars()
+        .all(|c| matches!(c as u32, 32..=126 | 161..=255));
+    if keyword.is_empty()
+        || keyword.chars().count() > 79
+        || !printable
+        || keyword.starts_with(' ')
+        || keyword.ends_with(' ')
+        || keyword.contains("  ")
+    {
+        return Err(Error::from(format!(
+            "{} `{}` must be 1 to 79 printable Latin-1 characters",
+            what, keyword
+        )));
+    }
+    Ok(())
+}
+
+/// cICP: coding-independent code points of ITU-T H.273
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cicp {
+    pub color_primaries: u8,
+    pub transfer_function: u8,
+    /// PNG only stores RGB, so this must be 0
+    pub matrix_coefficients: u8,
+    pub full_range: bool,
+}
+
+impl Cicp {
+    pub fn parse(data: &[u8]) -> Result<Cicp> {
+        check_len("cICP", data, 4)?;
+        let cicp = Cicp {
+            color_primaries: data[0],
+            transfer_function: data[1],
+            matrix_coefficients: data[2],
+            full_range: match data[3] {
+                0 => false,
+                1 => true,
+                flag => {
+                    return Err(Error::from(format!(
+                        "cICP full range flag must be 0 or 1, found {}",
+                        flag
+                    )))
+                }
+            },
+        };
+        cicp.validate()?;
+        Ok(cicp)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.matrix_coefficients != 0 {
+            return Err(Error::from(
+                "cICP matrix coefficients must be 0, PNG stores RGB samples",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(self) -> Result<Vec<u8>> {
+        self.validate()?;
+        Ok(vec![
+            self.color_primaries,
+            self.transfer_function,
+            self.matrix_coefficients,
+            self.full_range as u8,
+        ])
+    }
+}
+
+impl FromStr for Cicp {
+    type Err = Error;
+    /// primaries, transfer function, matrix coefficients and the full range flag, like `9,16,0,1`
+    fn from_str(s: &str) -> Result<Cicp> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u8>())
+            .collect::<std::result::Result<Vec<u8>, _>>()?;
+        match values.as_slice() {
+            [primaries, transfer, matrix, full_range] => {
+                Cicp::parse(&[*primaries, *transfer, *matrix, *full_range])
+            }
+            _ => Err(Error::from(
+                "cICP needs 4 values: primaries,transfer,matrix,full-range",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Cicp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let primaries = match self.color_primaries {
+            1 => " (BT.709)",
+            9 => " (BT.2020)",
+            11 => " (DCI-P3)",
+            12 => " (Display P3)",
+            _ => "",
+        };
+        let transfer = match self.transfer_function {
+            1 => " (BT.709)",
+            8 => " (linear)",
+            13 => " (sRGB)",
+            16 => " (PQ)",
+            18 => " (HLG)",
+            _ => "",
+        };
+        write!(
+            f,
+            "primaries {}{} transfer {}{} matrix {} {} range",
+            self.color_primaries,
+            primaries,
+            self.transfer_function,
+            transfer,
+            self.matrix_coefficients,
+            if self.full_range { "full" } else { "narrow" }
+        )
+    }
+}
+
+/// mDCv stores chromaticities in units of 0.00002, dividing keeps the printed values short
+const HDR_CHROMATICITY_SCALE: f64 = 50_000.0;
+
+/// mDCv and cLLi store luminances in units of 0.0001 cd/m²
+const LUMINANCE_SCALE: f64 = 10_000.0;
+
+/// HDR chunks, they must come before IDAT
+pub const HDR_CHUNK_TYPES: [&str; 2] = ["mDCv", "cLLi"];
+
+/// mDCv: the color volume of the display the content was mastered on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MasteringDisplay {
+    /// x and y of red, green and blue in 0.00002 units
+    pub primaries: [(u16, u16); 3],
+    /// x and y of the white point in 0.00002 units
+    pub white: (u16, u16),
+    /// in 0.0001 cd/m²
+    pub max_luminance: u32,
+    /// in 0.0001 cd/m²
+    pub min_luminance: u32,
+}
+
+impl MasteringDisplay {
+    pub fn parse(data: &[u8]) -> Result<MasteringDisplay> {
+        check_len("mDCv", data, 24)?;
+        let be_u16 = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let point = |i: usize| (be_u16(i * 4), be_u16(i * 4 + 2));
+        let display = MasteringDisplay {
+            primaries: [point(0), point(1), point(2)],
+            white: point(3),
+            max_luminance: be_u32(data, 16),
+            min_luminance: be_u32(data, 20),
+        };
+        if display.min_luminance >= display.max_luminance {
+            return Err(Error::from(
+                "mDCv minimum luminance must be below the maximum",
+            ));
+        }
+        Ok(display)
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let points: Vec<u8> = self
+            .primaries
+            .iter()
+            .chain([&self.white])
+            .flat_map(|(x, y)| [x.to_be_bytes(), y.to_be_bytes()].concat())
+            .collect();
+        [
+            points,
+            self.max_luminance.to_be_bytes().to_vec(),
+            self.min_luminance.to_be_bytes().to_vec(),
+        ]
+        .concat()
+    }
+
+    /// the chromaticity of a stored x or y value
+    pub fn chromaticity(value: u16) -> f64 {
+        value as f64 / HDR_CHROMATICITY_SCALE
+    }
+
+    /// in cd/m²
+    pub fn max_nits(&self) -> f64 {
+        self.max_luminance as f64 / LUMINANCE_SCALE
+    }
+
+    /// in cd/m²
+    pub fn min_nits(&self) -> f64 {
+        self.min_luminance as f64 / LUMINANCE_SCALE
+    }
+}
+
+impl fmt::Display for MasteringDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let point = |(x, y): (u16, u16)| {
+            format!(
+                "({}, {})",
+                MasteringDisplay::chromaticity(x),
+                MasteringDisplay::chromaticity(y)
+            )
+        };
+        write!(
+            f,
+            "red {} green {} blue {} white {} luminance {} to {} cd/m²",
+            point(self.primaries[0]),
+            point(self.primaries[1]),
+            point(self.primaries[2]),
+            point(self.white),
+            self.min_nits(),
+            self.max_nits()
+        )
+    }
+}
+
+/// cLLi: the brightest pixel and the brightest frame average of the content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLightLevel {
+    /// MaxCLL in 0.0001 cd/m²
+    pub max_content: u32,
+    /// MaxFALL in 0.0001 cd/m²
+    pub max_frame_average: u32,
+}
+
+impl ContentLightLevel {
+    pub fn parse(data: &[u8]) -> Result<ContentLightLevel> {
+        check_len("cLLi", data, 8)?;
+        Ok(ContentLightLevel {
+            max_content: be_u32(data, 0),
+            max_frame_average: be_u32(data, 4),
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [
+            self.max_content.to_be_bytes(),
+            self.max_frame_average.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// MaxCLL in cd/m²
+    pub fn max_content_nits(&self) -> f64 {
+        self.max_content as f64 / LUMINANCE_SCALE
+    }
+
+    /// MaxFALL in cd/m²
+    pub fn max_frame_average_nits(&self) -> f64 {
+        self.max_frame_average as f64 / LUMINANCE_SCALE
+    }
+}
+
+impl fmt::Display for ContentLightLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MaxCLL {} cd/m² MaxFALL {} cd/m²",
+            self.max_content_nits(),
+            self.max_frame_average_nits()
+        )
+    }
+}
+
+/// the HDR chunks of a png
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HdrInfo {
+    pub mastering_display: Option<MasteringDisplay>,
+    pub light_level: Option<ContentLightLevel>,
+}
+
+impl HdrInfo {
+    pub fn from_png(png: &Png) -> Result<HdrInfo> {
+        let data = |chunk_type: &str| png.chunk_by_type(chunk_type).map(|chunk| chunk.data());
+        Ok(HdrInfo {
+            mastering_display: data("mDCv").map(MasteringDisplay::parse).transpose()?,
+            light_level: data("cLLi").map(ContentLightLevel::parse).transpose()?,
+        })
+    }
+
+    /// write the present chunks into the png and remove the missing ones
+    pub fn write_to(&self, png: &mut Png) {
+        let chunks = [
+            self.mastering_display.map(MasteringDisplay::to_bytes),
+            self.light_level.map(ContentLightLevel::to_bytes),
+        ];
+        for (chunk_type, data) in HDR_CHUNK_TYPES.iter().zip(chunks) {
+            match data {
+                Some(data) => {
+                    png.set_chunk_before(Chunk::new(chunk_type.parse().unwrap(), data), &["IDAT"])
+                }
+                None => {
+                    png.remove_chunks_where(chunk_type, |_| true);
+                }
+            }
+        }
+    }
+}
+
+/// color and HDR chunks that appear more than once or after the chunks they must precede
+pub fn placement_errors(png: &Png) -> Vec<String> {
+    let types: Vec<String> = png
+        .chunks()
+        .iter()
+        .map(|chunk| chunk.chunk_type().to_string())
+        .collect();
+    let first = |chunk_type: &str| types.iter().position(|t| t == chunk_type);
+    let mut errors = Vec::new();
+    let rules = COLOR_CHUNK_TYPES
+        .iter()
+        .map(|chunk_type| (*chunk_type, COLOR_CHUNKS_BEFORE.as_ref()))
+        .chain(
+            HDR_CHUNK_TYPES
+                .iter()
+                .map(|chunk_type| (*chunk_type, ["IDAT"].as_ref())),
+        );
+    for (chunk_type, before) in rules {
+        let positions: Vec<usize> = (0..types.len())
+            .filter(|i| types[*i] == chunk_type)
+            .collect();
+        if positions.len() > 1 {
+            errors.push(format!(
+                "{} appears {} times, only one is allowed",
+                chunk_type,
+                positions.len()
+            ));
+        }
+        for limit in before.iter() {
+            if let (Some(position), Some(limit_position)) = (positions.first(), first(limit)) {
+                if *position > limit_position {
+                    errors.push(format!("{} must come before {}", chunk_type, limit));
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// every color chunk of a png
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColorInfo {
+    pub gamma: Option<Gamma>,
+    pub chromaticities: Option<Chromaticities>,
+    pub srgb: Option<RenderingIntent>,
+    pub icc: Option<IccProfile>,
+    pub cicp: Option<Cicp>,
+}
+
+impl ColorInfo {
+    pub fn from_png(png: &Png) -> Result<ColorInfo> {
+        let data = |chunk_type: &str| png.chunk_by_type(chunk_type).map(|chunk| chunk.data());
+        Ok(ColorInfo {
+            gamma: data("gAMA").map(Gamma::parse).transpose()?,
+            chromaticities: data("cHRM").map(Chromaticities::parse).transpose()?,
+            srgb: data("sRGB").map(RenderingIntent::parse).transpose()?,
+            icc: data("iCCP").map(IccProfile::parse).transpose()?,
+            cicp: data("cICP").map(Cicp::parse).transpose()?,
+        })
+    }
+
+    /// the chunk decoders will use for the color space
+    pub fn effective(&self) -> Option<&'static str> {
+        [
+            self.cicp.is_some(),
+            self.icc.is_some(),
+            self.srgb.is_some(),
+            self.chromaticities.is_some(),
+            self.gamma.is_some(),
+        ]
+        .iter()
+        .position(|present| *present)
+        .map(|i| COLOR_CHUNK_TYPES[i])
+    }
+
+    /// combinations the spec advises against or that decoders will partly ignore
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.icc.is_some() && self.srgb.is_some() {
+            warnings.push(String::from(
+                "iCCP and sRGB should not both be present, decoders use iCCP",
+            ));
+        }
+        if self.cicp.is_some() && (self.icc.is_some() || self.srgb.is_some()) {
+            warnings.push(String::from(
+                "cICP takes precedence, iCCP and sRGB are only used by decoders without cICP support",
+            ));
+        }
+        let overriding = if self.cicp.is_some() {
+            Some("cICP")
+        } else if self.icc.is_some() {
+            Some("iCCP")
+        } else {
+            None
+        };
+        if let Some(overriding) = overriding {
+            if self.gamma.is_some() || self.chromaticities.is_some() {
+                warnings.push(format!(
+                    "gAMA and cHRM are overridden by {} and only serve as a fallback",
+                    overriding
+                ));
+            }
+        }
+        if self.srgb.is_some() {
+            if self.gamma.is_some_and(|gamma| gamma.0 != SRGB_GAMMA) {
+                warnings.push(format!(
+                    "gAMA should be {} with sRGB, found {}",
+                    SRGB_GAMMA,
+                    self.gamma.unwrap().0
+                ));
+            }
+            if self
+                .chromaticities
+                .is_some_and(|chromaticities| chromaticities != SRGB_CHROMATICITIES)
+            {
+                warnings.push(String::from(
+                    "cHRM should hold the sRGB primaries and white point with sRGB",
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// write the present chunks into the png and remove the missing ones
+    pub fn write_to(&self, png: &mut Png) -> Result<()> {
+        let chunks = [
+            self.cicp.map(Cicp::to_bytes).transpose()?,
+            self.icc.as_ref().map(IccProfile::to_bytes).transpose()?,
+            self.srgb.map(RenderingIntent::to_bytes),
+            self.chromaticities.map(Chromaticities::to_bytes),
+            self.gamma.map(Gamma::to_bytes),
+        ];
+        for (chunk_type, data) in COLOR_CHUNK_TYPES.iter().zip(chunks) {
+            match data {
+                Some(data) => png.set_chunk_before(
+                    Chunk::new(chunk_type.parse().unwrap(), data),
+                    &COLOR_CHUNKS_BEFORE,
+                ),
+                None => {
+                    png.remove_chunks_where(chunk_type, |_| true);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gamma() {
+        assert_eq!(Gamma::from_str("0.45455").unwrap(), Gamma(SRGB_GAMMA));
+        assert_eq!(Gamma::from_str("1/2.2").unwrap(), Gamma(SRGB_GAMMA));
+        assert_eq!(
+            Gamma::parse(&Gamma(100000).to_bytes()).unwrap(),
+            Gamma(100000)
+        );
+        assert!(Gamma::parse(&[0, 0, 0, 0]).is_err());
+        assert!(Gamma::from_str("0").is_err());
+    }
+
+    #[test]
+    fn test_chromaticities() {
+        let parsed = Chromaticities::from_str("0.3127,0.329,0.64,0.33,0.3,0.6,0.15,0.06").unwrap();
+        assert_eq!(parsed, SRGB_CHROMATICITIES);
+        assert_eq!(
+            Chromaticities::parse(&parsed.to_bytes()).unwrap(),
+            SRGB_CHROMATICITIES
+        );
+        assert!(Chromaticities::from_str("0.1,0.2").is_err());
+        assert!(Chromaticities::parse(&[0; 31]).is_err());
+    }
+
+    #[test]
+    fn test_rendering_intent() {
+        let intent = RenderingIntent::from_str("saturation").unwrap();
+        assert_eq!(intent.to_bytes(), vec![2]);
+        assert_eq!(
+            RenderingIntent::parse(&[3]).unwrap().to_string(),
+            "absolute-colorimetric"
+        );
+        assert!(RenderingIntent::parse(&[4]).is_err());
+    }
+
+    #[test]
+    fn test_icc_profile() {
+        let icc = IccProfile {
+            name: String::from("Display P3"),
+            profile: b"acspAPPL".repeat(100),
+        };
+        let bytes = icc.to_bytes().unwrap();
+        assert!(bytes.starts_with(b"Display P3\0\0"));
+        assert_eq!(IccProfile::parse(&bytes).unwrap(), icc);
+
+        let unnamed = IccProfile {
+            name: String::from(" bad"),
+            profile: Vec::new(),
+        };
+        assert!(unnamed.to_bytes().is_err());
+        assert!(IccProfile::parse(b"name\0\x01abc").is_err());
+    }
+
+    #[test]
+    fn test_cicp() {
+        let cicp = Cicp::from_str("9,16,0,1").unwrap();
+        assert_eq!(cicp.to_bytes().unwrap(), vec![9, 16, 0, 1]);
+        assert_eq!(
+            cicp.to_string(),
+            "primaries 9 (BT.2020) transfer 16 (PQ) matrix 0 full range"
+        );
+        assert!(Cicp::from_str("9,16,1,1").is_err());
+        assert!(Cicp::parse(&[1, 13, 0, 2]).is_err());
+    }
+
+    #[test]
+    fn test_mastering_display() {
+        // BT.2020 primaries, D65 white, 0.005 to 1000 cd/m²
+        let display = MasteringDisplay {
+            primaries: [(35400, 14600), (8500, 39850), (6550, 2300)],
+            white: (15635, 16450),
+            max_luminance: 10_000_000,
+            min_luminance: 50,
+        };
+        let bytes = display.to_bytes();
+        assert_eq!(bytes.len(), 24);
+        assert_eq!(MasteringDisplay::parse(&bytes).unwrap(), display);
+        assert!((MasteringDisplay::chromaticity(35400) - 0.708).abs() < 1e-9);
+        assert!((display.max_nits() - 1000.0).abs() < 1e-9);
+        assert!((display.min_nits() - 0.005).abs() < 1e-9);
+
+        let inverted = MasteringDisplay {
+            min_luminance: 10_000_000,
+            ..display
+        };
+        assert!(MasteringDisplay::parse(&inverted.to_bytes()).is_err());
+        assert!(MasteringDisplay::parse(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn test_content_light_level() {
+        let level = ContentLightLevel::parse(&[0, 0x98, 0x96, 0x80, 0, 0x3d, 0x09, 0]).unwrap();
+        assert!((level.max_content_nits() - 1000.0).abs() < 1e-9);
+        assert!((level.max_frame_average_nits() - 400.0).abs() < 1e-9);
+        assert_eq!(level.to_string(), "MaxCLL 1000 cd/m² MaxFALL 400 cd/m²");
+        assert!(ContentLightLevel::parse(&[0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_placement() {
+        let mut png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        assert!(placement_errors(&png).is_empty());
+
+        let hdr = HdrInfo {
+            mastering_display: None,
+            light_level: Some(ContentLightLevel {
+                max_content: 1,
+                max_frame_average: 1,
+            }),
+        };
+        hdr.write_to(&mut png);
+        assert_eq!(HdrInfo::from_png(&png).unwrap(), hdr);
+        assert!(placement_errors(&png).is_empty());
+
+        png.insert_before_iend(Chunk::new("mDCv".parse().unwrap(), vec![0; 24]));
+        png.insert_before_iend(Chunk::new("gAMA".parse().unwrap(), vec![0, 0, 1, 0]));
+        assert_eq!(
+            placement_errors(&png),
+            vec![
+                "gAMA appears 2 times, only one is allowed",
+                "mDCv must come before IDAT"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_color_info() {
+        let mut png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let mut info = ColorInfo::from_png(&png).unwrap();
+        // the dice carry sRGB and a matching gAMA
+        assert_eq!(info.srgb, Some(RenderingIntent::Perceptual));
+        assert_eq!(info.gamma, Some(Gamma(SRGB_GAMMA)));
+        assert_eq!(info.effective(), Some("sRGB"));
+        assert!(info.warnings().is_empty());
+
+        info.cicp = Some(Cicp::from_str("12,13,0,1").unwrap());
+        info.icc = Some(IccProfile {
+            name: String::from("P3"),
+            profile: vec![1, 2, 3],
+        });
+        info.gamma = None;
+        assert_eq!(info.warnings().len(), 2);
+        info.write_to(&mut png).unwrap();
+
+        assert_eq!(ColorInfo::from_png(&png).unwrap(), info);
+        assert!(png.chunk_by_type("gAMA").is_none());
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        let position = |chunk_type: &str| types.iter().position(|t| t == chunk_type).unwrap();
+        assert!(position("cICP") < position("IDAT"));
+        assert!(position("iCCP") < position("IDAT"));
+    }
+}
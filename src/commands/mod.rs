@@ -4,83 +4,1008 @@ use std::{
     str::FromStr,
 };
 
-use crate::{args::*, chunk::Chunk, chunk_type::ChunkType, png::Png, Error, Result};
+use crate::{
+    analysis,
+    apng::{self, Animation},
+    args::*,
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    color::{self, ColorInfo, HdrInfo, IccProfile},
+    compression, crypto,
+    exif::{self, Exif},
+    image::{zlib, ColorType, Header, ImageData},
+    metadata::{self, Background, Histogram, Phys, SignificantBits, SuggestedPalette, Time},
+    optimize,
+    payload::{self, FilePayload, SequencePart},
+    png::Png,
+    signature,
+    stego::{self, LsbOptions, Method, StorageMethod},
+    Error, Result,
+};
 use std::fs::OpenOptions;
 
 /// get the png struct by the path of the input.
 fn take_png<T: AsRef<Path>>(input: T) -> Result<Png> {
-    let mut file_buffer = OpenOptions::new()
-        .write(true)
-        .read(true)
-        .open(input)
-        .unwrap();
+    let mut file_buffer = OpenOptions::new().write(true).read(true).open(input)?;
     let mut buffer = Vec::with_capacity(1000000);
-    file_buffer.read_to_end(&mut buffer);
-    // The following two lines equal
-    // Ok(buffer.as_slice().try_into().unwrap())
-    Ok(Png::try_from(buffer.as_slice()).unwrap())
+    file_buffer.read_to_end(&mut buffer)?;
+    Png::try_from(buffer.as_slice())
+}
+
+/// environment variable checked before prompting for a passphrase
+const PASSPHRASE_ENV: &str = "PNG_INFO_PASSPHRASE";
+
+/// read the passphrase from the environment, or prompt for it on the terminal.
+fn read_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Passphrase: ")?;
+    if confirm && passphrase != rpassword::prompt_password("Confirm passphrase: ")? {
+        return Err(Error::from("passphrases do not match"));
+    }
+    Ok(passphrase)
+}
+
+/// the chunk type and the bytes to hide: `<chunk_type> --message <text>` or
+/// `<chunk_type> --file <path>`, without the chunk type for the pixel methods
+fn encode_source(args: &EncodeArgs) -> Result<(Option<String>, Vec<u8>)> {
+    // `encode <message> <chunk-type>` shifts the chunk type to the second argument
+    let (data, chunk_type) = match (&args.message, &args.file, &args.second_chunk_type) {
+        (Some(message), None, None) => (message.clone().into_bytes(), args.chunk_type.clone()),
+        (None, Some(path), None) => (FilePayload::read(path)?.to_bytes(), args.chunk_type.clone()),
+        (None, None, Some(chunk_type)) => match &args.chunk_type {
+            Some(message) => (message.clone().into_bytes(), Some(chunk_type.clone())),
+            None => return Err(Error::from("missing the message")),
+        },
+        _ => return Err(Error::from("give either a message or --file")),
+    };
+    match (args.method, &chunk_type) {
+        (Method::Chunk, None) => Err(Error::from("missing the chunk type")),
+        (Method::Lsb | Method::Palette, Some(_)) => Err(Error::from(
+            "the payload is hidden in the pixels, no chunk type is needed",
+        )),
+        _ => Ok((chunk_type, data)),
+    }
+}
+
+/// whether the APNG frame sequence parses, a png without acTL counts as valid
+fn animation_valid(png: &Png) -> bool {
+    Animation::from_png(png).is_ok()
+}
+
+/// write the png back, refusing to when the edit broke a frame sequence that was valid before.
+/// an already broken animation does not block edits, like removing its chunks
+fn save<T: AsRef<Path>>(input: T, png: &Png, valid_before: bool) -> Result<()> {
+    if valid_before {
+        if let Err(e) = Animation::from_png(png) {
+            return Err(Error::from(format!(
+                "not written, the change would break the animation: {}",
+                e
+            )));
+        }
+    }
+    std::fs::write(input, png.as_bytes())?;
+    Ok(())
+}
+
+/// everything that makes the png structurally invalid
+pub fn validation_errors(png: &Png) -> Vec<String> {
+    let mut errors = Vec::new();
+    if let Err(e) = png.palette() {
+        errors.push(format!("PLTE: {}", e));
+    }
+    if let Err(e) = png.transparency() {
+        errors.push(format!("tRNS: {}", e));
+    }
+    if let Err(e) = Animation::from_png(png) {
+        errors.push(format!("APNG: {}", e));
+    }
+    match Header::from_png(png).and_then(|header| header.raw_len()) {
+        Err(e) => errors.push(e.to_string()),
+        Ok(_) => errors.extend(
+            zlib::stream_errors(png)
+                .into_iter()
+                .map(|e| format!("IDAT: {}", e)),
+        ),
+    }
+    errors.extend(color::placement_errors(png));
+    errors.extend(metadata::placement_errors(png));
+    errors
+}
+
+/// set tIME to now when `--touch` is given, as the spec wants it to follow modifications
+fn touch(png: &mut Png, enabled: bool) -> Result<()> {
+    if enabled {
+        Time::now()?.write_to(png);
+    }
+    Ok(())
+}
+
+/// `passphrase` is needed when the payload is scattered
+fn lsb_options(args: &LsbArgs, passphrase: Option<&str>) -> Result<LsbOptions> {
+    let seed = if args.scatter {
+        let passphrase = passphrase.ok_or_else(|| Error::from("--scatter needs a passphrase"))?;
+        Some(crypto::derive_seed(passphrase, stego::ORDER_SEED_CONTEXT)?)
+    } else {
+        None
+    };
+    Ok(LsbOptions {
+        bits: args.bits,
+        channels: args.channels.clone().unwrap_or_default(),
+        include_alpha: args.include_alpha,
+        include_transparent: args.include_transparent,
+        seed,
+    })
 }
 
 pub fn encode<T: AsRef<Path>>(input: T, args: EncodeArgs) -> Result<()> {
-    let mut png_item = take_png(&input).unwrap();
-    png_item.append_chunk(Chunk::new(
-        ChunkType::from_str(&args.chunk_type).unwrap(),
-        args.message.into_bytes(),
-    ));
-    let mut file = std::fs::File::create(input).unwrap();
-    file.write_all(&png_item.as_bytes()).unwrap();
+    let mut png_item = take_png(&input)?;
+    let valid_before = animation_valid(&png_item);
+    let (chunk_type, mut data) = encode_source(&args)?;
+    if let Some(method) = args.compress {
+        data = compression::compress(&data, method)?;
+    }
+    // read once, both the encryption and the scattering use it
+    let passphrase = if args.encrypt || args.lsb.scatter {
+        Some(read_passphrase(true)?)
+    } else {
+        None
+    };
+    if let Some(passphrase) = passphrase.as_deref().filter(|_| args.encrypt) {
+        data = crypto::seal_with_passphrase(&data, passphrase)?;
+    } else if !args.recipients.is_empty() {
+        let recipients = args
+            .recipients
+            .iter()
+            .map(|path| crypto::public_key_from_string(&std::fs::read_to_string(path)?))
+            .collect::<Result<Vec<_>>>()?;
+        data = crypto::seal_for_recipients(&data, &recipients)?;
+    }
+
+    match chunk_type {
+        Some(chunk_type) => {
+            let chunk_type =
+                ChunkType::from_str(&chunk_type).map_err(|e| Error::from(e.to_string()))?;
+            // the parts go before IEND, after the image data and in an APNG after the last frame
+            for part in payload::split(&data, args.split_size.unwrap_or(Chunk::MAX_LENGTH))? {
+                png_item.insert_before_iend(Chunk::new(chunk_type.clone(), part));
+            }
+        }
+        None => {
+            let options = lsb_options(&args.lsb, passphrase.as_deref())?;
+            if args.method == Method::Palette {
+                if png_item.chunk_by_type("acTL").is_some() {
+                    return Err(Error::from(
+                        "the palette method can not be used on an APNG, its frames share the palette",
+                    ));
+                }
+                stego::palette::embed(&mut png_item, &data, options.seed)?;
+            } else {
+                let mut image = ImageData::decode(&png_item)?;
+                stego::embed(&mut image, &data, &options)?;
+                image.write_to(&mut png_item)?;
+            }
+        }
+    }
+    touch(&mut png_item, args.touch)?;
+    save(&input, &png_item, valid_before)?;
     Ok(())
 }
 
 /// decode the info
 pub fn decode<T: AsRef<Path>>(input: T, args: DecodeArgs) -> Result<()> {
-    let mut png_item = take_png(&input).unwrap();
-    if let Some(target_chunk) = png_item.chunk_by_type(&args.chunk_type) {
+    let png_item = take_png(&input)?;
+    let mut passphrase = None;
+    let (data, location) = match (args.method, &args.chunk_type) {
+        (Method::Chunk, Some(chunk_type)) => {
+            let target_chunk = png_item
+                .chunk_by_type(chunk_type)
+                .ok_or_else(|| Error::from("can not find the chunk_type"))?;
+            let data = if SequencePart::is_sequence_part(target_chunk.data()) {
+                payload::join(
+                    png_item
+                        .chunks_by_type(chunk_type)
+                        .into_iter()
+                        .map(|chunk| chunk.data()),
+                )?
+            } else {
+                target_chunk.data().to_vec()
+            };
+            (data, target_chunk.chunk_type().to_string())
+        }
+        (Method::Chunk, None) => return Err(Error::from("missing the chunk type")),
+        (Method::Lsb, None) => {
+            let image = ImageData::decode(&png_item)?;
+            if args.lsb.scatter {
+                passphrase = Some(read_passphrase(false)?);
+            }
+            let data = stego::extract(&image, &lsb_options(&args.lsb, passphrase.as_deref())?)?;
+            (data, String::from("pixels"))
+        }
+        (Method::Palette, None) => {
+            if args.lsb.scatter {
+                passphrase = Some(read_passphrase(false)?);
+            }
+            let seed = lsb_options(&args.lsb, passphrase.as_deref())?.seed;
+            (
+                stego::palette::extract(&png_item, seed)?,
+                String::from("palette"),
+            )
+        }
+        (Method::Lsb | Method::Palette, Some(_)) => {
+            return Err(Error::from(
+                "the payload is hidden in the pixels, no chunk type is needed",
+            ))
+        }
+    };
+
+    let data = if crypto::is_passphrase_sealed(&data) {
+        let passphrase = match passphrase {
+            Some(passphrase) => passphrase,
+            None => read_passphrase(false)?,
+        };
+        crypto::open_with_passphrase(&data, &passphrase)?
+    } else if crypto::is_recipient_sealed(&data) {
+        let path = args
+            .identity
+            .as_ref()
+            .ok_or_else(|| Error::from("the message is sealed, pass --identity <key-file>"))?;
+        let identity = crypto::Identity::from_key_string(&std::fs::read_to_string(path)?)?;
+        crypto::open_with_identity(&data, &identity)?
+    } else {
+        data
+    };
+    let data = if compression::is_compressed(&data) {
+        compression::decompress(
+            &data,
+            args.max_size.unwrap_or(compression::DEFAULT_MAX_SIZE),
+        )?
+    } else {
+        data
+    };
+
+    if FilePayload::is_file_payload(&data) {
+        let file = FilePayload::try_from(data.as_slice())?;
+        let output = args.output.as_ref().ok_or_else(|| {
+            Error::from(format!(
+                "the {} holds the file {} ({}, {} bytes), pass --output <path>",
+                location,
+                file.name,
+                file.mime,
+                file.data.len()
+            ))
+        })?;
+        std::fs::write(output, &file.data)?;
         println!(
-            "Hidden message is {},in the {}",
-            target_chunk.data_as_string().unwrap(),
-            target_chunk.chunk_type().to_string()
+            "Hidden file {} ({}, {} bytes) in the {} written to {}",
+            file.name,
+            file.mime,
+            file.data.len(),
+            location,
+            output.display()
+        );
+    } else if let Some(output) = &args.output {
+        std::fs::write(output, &data)?;
+        println!(
+            "Hidden message ({} bytes) in the {} written to {}",
+            data.len(),
+            location,
+            output.display()
         );
     } else {
-        return Err(Error::from("can not find the chunk_type"));
+        let message = String::from_utf8(data).map_err(|_| {
+            Error::from("the hidden data is not text, pass --output <path> to save it")
+        })?;
+        println!("Hidden message is {},in the {}", message, location);
     }
     Ok(())
 }
 
-/// remove the specific chunk
+/// remove the specific chunk, or every part of the split payload it starts
 pub fn remove<T: AsRef<Path>>(input: T, args: RemoveArgs) -> Result<()> {
-    let mut png_item = take_png(&input).unwrap();
+    let mut png_item = take_png(&input)?;
+    let valid_before = animation_valid(&png_item);
 
-    png_item.remove_chunk(&args.chunk_type);
-    let mut file = std::fs::File::create(input).unwrap();
-    file.write_all(&png_item.as_bytes()).unwrap();
+    match &args.chunk_type {
+        Some(chunk_type) => {
+            let removed = png_item.remove_chunk(chunk_type)?;
+            if let Ok(part) = SequencePart::parse(removed.data()) {
+                let id = part.id;
+                png_item.remove_chunks_where(chunk_type, |chunk| {
+                    SequencePart::parse(chunk.data()).is_ok_and(|part| part.id == id)
+                });
+            }
+        }
+        None => {
+            let removed: usize = apng::ANIMATION_CHUNK_TYPES
+                .iter()
+                .map(|chunk_type| png_item.remove_chunks_where(chunk_type, |_| true))
+                .sum();
+            if removed == 0 {
+                return Err(Error::from("not an animated png, there is no acTL chunk"));
+            }
+        }
+    }
+    touch(&mut png_item, args.touch)?;
+    save(&input, &png_item, valid_before)?;
     Ok(())
 }
 
 /// print the relative infomation
 pub fn print(input: &Path) -> Result<()> {
-    let mut png_item = take_png(&input).unwrap();
+    let png_item = take_png(input)?;
 
     println!(
         "File: {}, Size: {}KB",
         input.display(),
         png_item.as_bytes().len() / 1024
     );
+    match png_item.palette() {
+        Ok(Some(palette)) => println!("palette: {} entries", palette.len()),
+        Ok(None) => {}
+        Err(e) => println!("palette: invalid, {}", e),
+    }
+    match png_item.transparency() {
+        Ok(Some(transparency)) => println!("transparency: {}", transparency),
+        Ok(None) => {}
+        Err(e) => println!("transparency: invalid, {}", e),
+    }
+    match HdrInfo::from_png(&png_item) {
+        Ok(hdr) => {
+            if let Some(display) = hdr.mastering_display {
+                println!("mDCv: {}", display);
+            }
+            if let Some(level) = hdr.light_level {
+                println!("cLLi: {}", level);
+            }
+        }
+        Err(e) => println!("HDR metadata: invalid, {}", e),
+    }
+    match Phys::from_png(&png_item) {
+        Ok(Some(phys)) => println!("pHYs: {}", phys),
+        Ok(None) => {}
+        Err(e) => println!("pHYs: invalid, {}", e),
+    }
+    match Background::from_png(&png_item) {
+        Ok(Some(background)) => println!("bKGD: {}", background),
+        Ok(None) => {}
+        Err(e) => println!("bKGD: invalid, {}", e),
+    }
+    match Histogram::from_png(&png_item) {
+        Ok(Some(histogram)) => println!("hIST: {}", histogram),
+        Ok(None) => {}
+        Err(e) => println!("hIST: invalid, {}", e),
+    }
+    match SignificantBits::from_png(&png_item) {
+        Ok(Some(bits)) => println!("sBIT: {}", bits),
+        Ok(None) => {}
+        Err(e) => println!("sBIT: invalid, {}", e),
+    }
+    match SuggestedPalette::from_png(&png_item) {
+        Ok(palettes) => {
+            for palette in palettes {
+                println!("sPLT: {}", palette);
+            }
+        }
+        Err(e) => println!("sPLT: invalid, {}", e),
+    }
+    match Time::from_png(&png_item) {
+        Ok(Some(time)) => println!("tIME: {}", time),
+        Ok(None) => {}
+        Err(e) => println!("tIME: invalid, {}", e),
+    }
+    match Exif::from_png(&png_item) {
+        Ok(Some(exif)) => {
+            let gps = if exif.has_gps() { ", with GPS" } else { "" };
+            println!("eXIf: {} tags{}", exif.tags.len(), gps);
+            for line in exif.to_string().lines() {
+                println!("    {}", line);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => println!("eXIf: invalid, {}", e),
+    }
+    let placement_errors = color::placement_errors(&png_item)
+        .into_iter()
+        .chain(metadata::placement_errors(&png_item));
+    for error in placement_errors {
+        println!("warning: {}", error);
+    }
 
-    for (i, chunk) in png_item.chunks().iter().enumerate() {
-        if i > 10 {
-            return Ok(());
+    // the frames are shown as a timeline instead of their fcTL and fdAT chunks
+    let animation = match Animation::from_png(&png_item) {
+        Ok(animation) => animation,
+        Err(e) => {
+            println!("APNG: invalid, {}", e);
+            None
+        }
+    };
+    let chunks = png_item.chunks().iter().enumerate().filter(|(_, chunk)| {
+        animation.is_none()
+            || !apng::ANIMATION_CHUNK_TYPES.contains(&chunk.chunk_type().to_string().as_str())
+    });
+    for (listed, (i, chunk)) in chunks.enumerate() {
+        if listed > 10 {
+            break;
         }
         println!("{} {}", i, chunk);
     }
+    if let Some(animation) = animation {
+        print!("APNG: {}", animation);
+    }
+
+    Ok(())
+}
+
+/// write a new identity to the path and its public key next to it with a `.pub` suffix
+pub fn keygen(output: &Path, args: KeygenArgs) -> Result<()> {
+    let (secret, public) = if args.signing {
+        let key = signature::generate_signing_key();
+        (
+            signature::signing_key_to_string(&key),
+            signature::verifying_key_to_string(&key.verifying_key()),
+        )
+    } else {
+        let identity = crypto::Identity::generate();
+        (
+            identity.to_key_string(),
+            crypto::public_key_to_string(&identity.public_key()),
+        )
+    };
+    let mut public_path = output.as_os_str().to_owned();
+    public_path.push(".pub");
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    // the identity is a secret, keep it readable by the owner only
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(output)?.write_all(secret.as_bytes())?;
+    std::fs::write(&public_path, public)?;
+    println!(
+        "secret key written to {}, public key written to {}",
+        output.display(),
+        Path::new(&public_path).display()
+    );
+    Ok(())
+}
+
+/// sign the file, an existing signature is replaced
+pub fn sign<T: AsRef<Path>>(input: T, args: SignArgs) -> Result<()> {
+    let mut png_item = take_png(&input)?;
+    let valid_before = animation_valid(&png_item);
+    let key = signature::signing_key_from_string(&std::fs::read_to_string(&args.key)?)?;
+    let extra = args
+        .include
+        .iter()
+        .map(|chunk_type| ChunkType::from_str(chunk_type).map_err(|e| Error::from(e.to_string())))
+        .collect::<Result<Vec<_>>>()?;
 
+    while png_item
+        .remove_chunk(signature::SIGNATURE_CHUNK_TYPE)
+        .is_ok()
+    {}
+    touch(&mut png_item, args.touch)?;
+    let signature_chunk = signature::sign(&png_item, &key, &extra);
+    png_item.insert_before_iend(signature_chunk);
+    save(&input, &png_item, valid_before)?;
+    Ok(())
+}
+
+/// verify the signature and list the covered chunks
+pub fn verify<T: AsRef<Path>>(input: T, args: VerifyArgs) -> Result<()> {
+    let png_item = take_png(&input)?;
+    let report = signature::verify(&png_item)?;
+
+    println!(
+        "signer: {}",
+        signature::verifying_key_to_string(&report.signer).trim()
+    );
+    let trusted = match args.key {
+        Some(path) => {
+            let key = signature::verifying_key_from_string(&std::fs::read_to_string(path)?)?;
+            let trusted = key == report.signer;
+            if !trusted {
+                println!("the signer does not match the trusted key");
+            }
+            trusted
+        }
+        None => {
+            println!("no trusted key given, the signer is not checked");
+            true
+        }
+    };
+    println!(
+        "signature: {}",
+        if report.signature_valid {
+            "valid"
+        } else {
+            "INVALID"
+        }
+    );
+    for (i, (chunk_type, status)) in report.chunks.iter().enumerate() {
+        println!("{} {} {:?}", i, chunk_type, status);
+    }
+    if report.reordered {
+        println!("the covered chunks are in a different order than when signed");
+    }
+
+    if trusted && report.is_intact() {
+        println!("all covered chunks are intact");
+        Ok(())
+    } else {
+        Err(Error::from("verification failed"))
+    }
+}
+
+pub fn capacity<T: AsRef<Path>>(input: T, args: CapacityArgs) -> Result<()> {
+    let png_item = take_png(&input)?;
+    println!(
+        "the headers of --file, --compress, --encrypt and --recipient count against these figures"
+    );
+
+    let chunk = png_item.capacity(&StorageMethod::Chunk { split_size: None })?;
+    println!("chunk: {} bytes in a single chunk", chunk);
+    if let Some(split_size) = args.split_size {
+        println!(
+            "chunk: {} bytes in each {} byte chunk when split, as many chunks as needed",
+            stego::split_part_capacity(split_size)?,
+            split_size
+        );
+    }
+
+    // only the pixel methods need the pixels, the figures above stay when they do not decode
+    let image = ImageData::decode(&png_item)?;
+    let header = &image.header;
+    if header.color_type == ColorType::Indexed {
+        println!(
+            "palette: {} bytes in twin palette entries",
+            png_item.capacity(&StorageMethod::Palette)?
+        );
+        println!("lsb: not used on indexed images, use --method palette");
+        return Ok(());
+    }
+    println!(
+        "lsb: {}x{} {:?} at {} bits{}",
+        header.width,
+        header.height,
+        header.color_type,
+        header.bit_depth,
+        if image.transparency.is_some() {
+            " with tRNS"
+        } else {
+            ""
+        }
+    );
+    for bits in 1..=header.bit_depth.min(4) {
+        let options = LsbOptions {
+            bits,
+            channels: args.channels.clone().unwrap_or_default(),
+            include_alpha: args.include_alpha,
+            include_transparent: args.include_transparent,
+            seed: None,
+        };
+        println!(
+            "lsb: {} bytes at {} bit(s) per channel",
+            stego::capacity(&image, &options)?,
+            bits
+        );
+    }
+    Ok(())
+}
+
+pub fn analyze(input: &Path) -> Result<()> {
+    let findings = analysis::analyze(&std::fs::read(input)?)?;
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    let suspicion = findings
+        .iter()
+        .map(|finding| finding.score)
+        .max()
+        .unwrap_or(0);
+    println!("suspicion: {}/100", suspicion);
+    Ok(())
+}
+
+/// show the color chunks after applying the strips first and then the new values
+pub fn color<T: AsRef<Path>>(input: T, args: ColorArgs) -> Result<()> {
+    let mut png_item = take_png(&input)?;
+    let valid_before = animation_valid(&png_item);
+    let mut info = ColorInfo::from_png(&png_item)?;
+    let mut hdr = HdrInfo::from_png(&png_item)?;
+    let original = (info.clone(), hdr.clone());
+
+    for chunk_type in &args.strip {
+        match chunk_type.as_str() {
+            "all" => (info, hdr) = (ColorInfo::default(), HdrInfo::default()),
+            "mDCv" => hdr.mastering_display = None,
+            "cLLi" => hdr.light_level = None,
+            "gAMA" => info.gamma = None,
+            "cHRM" => info.chromaticities = None,
+            "sRGB" => info.srgb = None,
+            "iCCP" => info.icc = None,
+            "cICP" => info.cicp = None,
+            _ => {
+                return Err(Error::from(format!(
+                    "`{}` is not a color chunk, expected one of {}, {} or all",
+                    chunk_type,
+                    color::COLOR_CHUNK_TYPES.join(", "),
+                    color::HDR_CHUNK_TYPES.join(", ")
+                )))
+            }
+        }
+    }
+    info.gamma = args.gamma.or(info.gamma);
+    info.chromaticities = args.chrm.or(info.chromaticities);
+    info.srgb = args.srgb.or(info.srgb);
+    info.cicp = args.cicp.or(info.cicp);
+    if let Some(path) = &args.icc {
+        let name = match &args.icc_name {
+            Some(name) => name.clone(),
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        info.icc = Some(IccProfile {
+            name,
+            profile: std::fs::read(path)?,
+        });
+    }
+
+    if (&info, &hdr) != (&original.0, &original.1) {
+        info.write_to(&mut png_item)?;
+        hdr.write_to(&mut png_item);
+        touch(&mut png_item, args.touch)?;
+        save(&input, &png_item, valid_before)?;
+    }
+    if let Some(gamma) = info.gamma {
+        println!("gAMA: {}", gamma);
+    }
+    if let Some(chromaticities) = info.chromaticities {
+        println!("cHRM: {}", chromaticities);
+    }
+    if let Some(intent) = info.srgb {
+        println!("sRGB: {} rendering intent", intent);
+    }
+    if let Some(icc) = &info.icc {
+        println!(
+            "iCCP: profile `{}` of {} bytes",
+            icc.name,
+            icc.profile.len()
+        );
+    }
+    if let Some(cicp) = info.cicp {
+        println!("cICP: {}", cicp);
+    }
+    if let Some(display) = hdr.mastering_display {
+        println!("mDCv: {}", display);
+    }
+    if let Some(level) = hdr.light_level {
+        println!("cLLi: {}", level);
+    }
+    match info.effective() {
+        Some(chunk_type) => println!("color space from {}", chunk_type),
+        None => println!("no color space chunks, decoders assume sRGB"),
+    }
+    for warning in info
+        .warnings()
+        .into_iter()
+        .chain(color::placement_errors(&png_item))
+    {
+        println!("warning: {}", warning);
+    }
+
+    if let Some(path) = args.extract_icc {
+        let icc = info
+            .icc
+            .as_ref()
+            .ok_or_else(|| Error::from("there is no iCCP chunk"))?;
+        std::fs::write(path, &icc.profile)?;
+    }
+    Ok(())
+}
+
+pub fn dpi<T: AsRef<Path>>(input: T, command: DpiCommands) -> Result<()> {
+    let mut png_item = take_png(&input)?;
+    let valid_before = animation_valid(&png_item);
+    match command {
+        DpiCommands::Show => match Phys::from_png(&png_item)? {
+            Some(phys) => println!("{}", phys),
+            None => println!("no pHYs chunk, the pixel density is unknown"),
+        },
+        DpiCommands::Set { x, y, touch: now } => {
+            let phys = Phys::from_dpi(x, y.unwrap_or(x))?;
+            phys.write_to(&mut png_item);
+            touch(&mut png_item, now)?;
+            save(&input, &png_item, valid_before)?;
+            println!("{}", phys);
+        }
+    }
+    Ok(())
+}
+
+pub fn exif<T: AsRef<Path>>(input: T, command: ExifCommands) -> Result<()> {
+    let mut png_item = take_png(&input)?;
+    let valid_before = animation_valid(&png_item);
+    let data = png_item
+        .chunk_by_type("eXIf")
+        .ok_or_else(|| Error::from("no eXIf chunk"))?
+        .data()
+        .to_vec();
+    match command {
+        ExifCommands::List => print!("{}", Exif::parse(&data)?),
+        ExifCommands::RemoveGps { touch: now } => {
+            let Some(data) = exif::remove_gps(&data)? else {
+                println!("no GPS tags");
+                return Ok(());
+            };
+            png_item.replace_chunk(Chunk::new("eXIf".parse().unwrap(), data))?;
+            touch(&mut png_item, now)?;
+            save(&input, &png_item, valid_before)?;
+            println!("removed the GPS tags");
+        }
+        ExifCommands::Remove { touch: now } => {
+            png_item.remove_chunk("eXIf")?;
+            touch(&mut png_item, now)?;
+            save(&input, &png_item, valid_before)?;
+            println!("removed eXIf");
+        }
+    }
+    Ok(())
+}
+
+pub fn time<T: AsRef<Path>>(input: T, command: TimeCommands) -> Result<()> {
+    let mut png_item = take_png(&input)?;
+    let valid_before = animation_valid(&png_item);
+    let time = match command {
+        TimeCommands::Show => {
+            match Time::from_png(&png_item)? {
+                Some(time) => println!("{}", time),
+                None => println!("no tIME chunk"),
+            }
+            return Ok(());
+        }
+        TimeCommands::Set { time } => time,
+        TimeCommands::Touch => Time::now()?,
+    };
+    time.write_to(&mut png_item);
+    save(&input, &png_item, valid_before)?;
+    println!("{}", time);
+    Ok(())
+}
+
+/// list the IDAT chunks, or split or merge them
+pub fn idat<T: AsRef<Path>>(input: T, args: IdatArgs) -> Result<()> {
+    let mut png_item = take_png(&input)?;
+    let valid_before = animation_valid(&png_item);
+    let before = png_item.chunks_by_type("IDAT").len();
+    match (args.split, args.merge) {
+        (Some(max_len), _) => png_item.rechunk_idat(max_len)?,
+        (None, true) => png_item.merge_idat()?,
+        (None, false) => {
+            for (i, chunk) in png_item.chunks_by_type("IDAT").iter().enumerate() {
+                println!("IDAT {}: {} bytes", i, chunk.length());
+            }
+            println!("{} bytes of image data", png_item.idat_stream().len());
+            return Ok(());
+        }
+    }
+    touch(&mut png_item, args.touch)?;
+    save(&input, &png_item, valid_before)?;
+    println!(
+        "{} IDAT chunks -> {} IDAT chunks, {} bytes of image data",
+        before,
+        png_item.chunks_by_type("IDAT").len(),
+        png_item.idat_stream().len()
+    );
+    Ok(())
+}
+
+/// the damage found while reading the file, like CRC mismatches and truncation, followed by the
+/// structural errors of the chunks that could be read
+pub fn file_errors(bytes: &[u8]) -> Result<Vec<String>> {
+    let (png, mut errors) = Png::parse(bytes)?;
+    errors.extend(validation_errors(&png));
+    Ok(errors)
+}
+
+/// report structural errors, fail when there is any
+pub fn validate(input: &Path) -> Result<()> {
+    let errors = file_errors(&std::fs::read(input)?)?;
+    for error in &errors {
+        println!("error: {}", error);
+    }
+    if !errors.is_empty() {
+        return Err(Error::from(format!("{} errors found", errors.len())));
+    }
+    println!("no errors found");
+    Ok(())
+}
+
+pub fn apng<T: AsRef<Path>>(input: T, command: ApngCommands) -> Result<()> {
+    match command {
+        ApngCommands::Extract { out_dir } => {
+            let png_item = take_png(&input)?;
+            let animation = Animation::from_png(&png_item)?
+                .ok_or_else(|| Error::from("not an animated png, there is no acTL chunk"))?;
+            std::fs::create_dir_all(&out_dir)?;
+            for (i, image) in animation.compose(&png_item)?.iter().enumerate() {
+                let path = out_dir.join(format!("frame_{:03}.png", i));
+                std::fs::write(&path, apng::still_png(image)?.as_bytes())?;
+                println!(
+                    "frame {} for {:.3}s written to {}",
+                    i,
+                    animation.frames[i].control.delay(),
+                    path.display()
+                );
+            }
+        }
+        ApngCommands::Build {
+            frames,
+            delay,
+            loops,
+            crop,
+        } => {
+            let stills = frames
+                .iter()
+                .map(|path| {
+                    let bytes = std::fs::read(path)?;
+                    Png::try_from(bytes.as_slice())
+                        .map_err(|e| Error::from(format!("{}: {}", path.display(), e)))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let png_item = apng::build(&stills, delay, loops, crop)?;
+            std::fs::write(&input, png_item.as_bytes())?;
+            print!("{}", Animation::from_png(&png_item)?.unwrap());
+        }
+    }
+    Ok(())
+}
+
+pub fn optimize<T: AsRef<Path>>(input: T, args: OptimizeArgs) -> Result<()> {
+    let mut png_item = take_png(&input)?;
+    let keep: Vec<&str> = if args.keep.is_empty() {
+        optimize::DEFAULT_KEEP.to_vec()
+    } else {
+        args.keep.iter().map(String::as_str).collect()
+    };
+    let mut optimized = optimize::optimize(&png_item, &keep)?;
+    let (before, after) = (png_item.as_bytes().len(), optimized.png.as_bytes().len());
+    let output = args.output.as_deref().unwrap_or(input.as_ref());
+    if after >= before {
+        println!(
+            "{} bytes, nothing smaller found, the best was {} bytes with {}",
+            before, after, optimized
+        );
+        if args.output.is_some() {
+            let valid_before = animation_valid(&png_item);
+            touch(&mut png_item, args.touch)?;
+            save(output, &png_item, valid_before)?;
+        }
+        return Ok(());
+    }
+    touch(&mut optimized.png, args.touch)?;
+    save(output, &optimized.png, true)?;
+    println!("{} bytes -> {} bytes with {}", before, after, optimized);
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
     #[test]
     fn get_file() {
         println!("{}", 23);
     }
+
+    #[test]
+    fn test_save_only_refuses_breaking_edits() {
+        let frame = || Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let animation = apng::build(&[frame(), frame(), frame()], 100, 0, false).unwrap();
+        let path = std::env::temp_dir().join(format!("png_info_save_{}.png", std::process::id()));
+        let remove_args = |chunk_type: Option<&str>| RemoveArgs {
+            chunk_type: chunk_type.map(String::from),
+            animation: chunk_type.is_none(),
+            touch: false,
+        };
+
+        std::fs::write(&path, animation.as_bytes()).unwrap();
+        assert!(remove(&path, remove_args(Some("acTL"))).is_err());
+        remove(&path, remove_args(None)).unwrap();
+        let still = take_png(&path).unwrap();
+        assert!(still.chunk_by_type("fcTL").is_none());
+        assert!(Animation::from_png(&still).unwrap().is_none());
+
+        // an animation that is already broken can still be edited
+        let mut broken = animation;
+        broken.remove_chunk("fcTL").unwrap();
+        std::fs::write(&path, broken.as_bytes()).unwrap();
+        time(&path, TimeCommands::Touch).unwrap();
+        remove(&path, remove_args(Some("acTL"))).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_before_iend() {
+        let path = std::env::temp_dir().join(format!("png_info_encode_{}.png", std::process::id()));
+        std::fs::write(&path, &crate::png::tests::PNG_FILE[..]).unwrap();
+        let args = EncodeArgs::from_iter_safe([
+            "encode",
+            "ruSt",
+            "--message",
+            "a message split into several parts",
+            "--split-size",
+            "30",
+        ])
+        .unwrap();
+        encode(&path, args).unwrap();
+        let png = take_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert!(png.chunks_by_type("ruSt").len() > 1);
+        assert_eq!(types.last().unwrap(), "IEND");
+        assert_eq!(types.iter().filter(|t| *t == "IEND").count(), 1);
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let frame = || Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let mut png = apng::build(&[frame(), frame(), frame()], 100, 0, false).unwrap();
+        assert!(validation_errors(&png).is_empty());
+
+        png.remove_chunk("fcTL").unwrap();
+        let errors = validation_errors(&png);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("APNG: "));
+
+        let header = Header {
+            width: Chunk::MAX_LENGTH as u32,
+            height: Chunk::MAX_LENGTH as u32,
+            bit_depth: 16,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let png = Png::from_chunks(vec![
+            Chunk::new("IHDR".parse().unwrap(), header.to_bytes()),
+            Chunk::new("IDAT".parse().unwrap(), vec![0x78, 0x9c]),
+            Chunk::new("IEND".parse().unwrap(), Vec::new()),
+        ]);
+        assert_eq!(validation_errors(&png), ["IHDR size overflows"]);
+    }
+
+    #[test]
+    fn test_file_errors() {
+        let mut bytes = crate::png::tests::PNG_FILE.to_vec();
+        assert!(file_errors(&bytes).unwrap().is_empty());
+
+        // flip a byte inside the first IDAT
+        let png = Png::try_from(&bytes[..]).unwrap();
+        let idat = 8 + png
+            .chunks()
+            .iter()
+            .take_while(|chunk| chunk.chunk_type().to_string() != "IDAT")
+            .map(|chunk| chunk.length() + 12)
+            .sum::<usize>();
+        bytes[idat + 20] ^= 0xff;
+        let errors = file_errors(&bytes).unwrap();
+        assert_eq!(
+            errors[0],
+            format!("CRC mismatch in IDAT 0 at byte {}", idat)
+        );
+        assert!(errors[1..].iter().any(|error| error.starts_with("IDAT: ")));
+
+        let len = bytes.len();
+        let errors = file_errors(&bytes[..len - 20]).unwrap();
+        let truncated = format!("truncated at byte {}", len - 20);
+        assert!(errors.iter().any(|error| error.starts_with(&truncated)));
+        assert!(Png::try_from(&bytes[..len - 20]).is_err());
+    }
 }
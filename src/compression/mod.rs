@@ -0,0 +1,199 @@
+/// compression of hidden payloads before they are sealed and embedded.
+/// the compressed payload starts with a small header naming the method, so `decode`
+/// can decompress it without being told how it was encoded.
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use std::{
+    fmt::{self, Display},
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use crate::{Error, Result};
+
+/// magic bytes at the start of a compressed payload
+pub const COMPRESSED_MAGIC: [u8; 4] = *b"PIFz";
+
+/// magic | method | original length (u64)
+const HEADER_LEN: usize = 4 + 1 + 8;
+
+/// default upper bound for the decompressed size, guards against decompression bombs
+pub const DEFAULT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Deflate,
+    Zstd,
+    Brotli,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::Deflate => 1,
+            Compression::Zstd => 2,
+            Compression::Brotli => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Compression> {
+        match id {
+            1 => Ok(Compression::Deflate),
+            2 => Ok(Compression::Zstd),
+            3 => Ok(Compression::Brotli),
+            _ => Err(Error::from(format!("unknown compression method {}", id))),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "deflate" => Ok(Compression::Deflate),
+            "zstd" => Ok(Compression::Zstd),
+            "brotli" => Ok(Compression::Brotli),
+            _ => Err(Error::from(format!(
+                "unknown compression `{}`, expected deflate, zstd or brotli",
+                s
+            ))),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Compression::Deflate => "deflate",
+            Compression::Zstd => "zstd",
+            Compression::Brotli => "brotli",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && data[0..4] == COMPRESSED_MAGIC
+}
+
+/// compress the data and prefix it with the header
+pub fn compress(data: &[u8], method: Compression) -> Result<Vec<u8>> {
+    let compressed = match method {
+        Compression::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        Compression::Zstd => zstd::encode_all(data, 19)?,
+        Compression::Brotli => {
+            let mut compressed = Vec::new();
+            {
+                let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+                writer.write_all(data)?;
+            }
+            compressed
+        }
+    };
+    Ok([
+        COMPRESSED_MAGIC.as_ref(),
+        &[method.id()],
+        (data.len() as u64).to_be_bytes().as_ref(),
+        compressed.as_slice(),
+    ]
+    .concat())
+}
+
+/// decompress a payload produced by `compress`, refusing to produce more than `max_size` bytes
+pub fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    if !is_compressed(data) {
+        return Err(Error::from("data is not a compressed payload"));
+    }
+    let method = Compression::from_id(data[4])?;
+    let length = u64::from_be_bytes(data[5..HEADER_LEN].try_into().unwrap());
+    if length > max_size as u64 {
+        return Err(Error::from(format!(
+            "the payload decompresses to {} bytes, more than the limit of {} bytes",
+            length, max_size
+        )));
+    }
+
+    let compressed = &data[HEADER_LEN..];
+    let reader: Box<dyn Read + '_> = match method {
+        Compression::Deflate => Box::new(DeflateDecoder::new(compressed)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(compressed)?),
+        Compression::Brotli => Box::new(brotli::Decompressor::new(compressed, 4096)),
+    };
+    // the declared length can lie, so the output is bounded as well
+    let mut output = Vec::new();
+    reader
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut output)
+        .map_err(|e| Error::from(format!("{} decompression failed: {}", method, e)))?;
+    if output.len() > max_size {
+        return Err(Error::from(format!(
+            "the payload decompresses to more than the limit of {} bytes",
+            max_size
+        )));
+    }
+    if output.len() as u64 != length {
+        return Err(Error::from(format!(
+            "the payload decompressed to {} bytes instead of {}",
+            output.len(),
+            length
+        )));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_data() -> Vec<u8> {
+        "{\"name\": \"png_info\", \"tags\": [\"hidden\", \"message\"]}\n"
+            .repeat(50)
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = testing_data();
+        for method in [Compression::Deflate, Compression::Zstd, Compression::Brotli] {
+            let compressed = compress(&data, method).unwrap();
+            assert!(is_compressed(&compressed));
+            assert!(compressed.len() < data.len() / 4, "{}", method);
+            assert_eq!(decompress(&compressed, DEFAULT_MAX_SIZE).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let data = testing_data();
+        let compressed = compress(&data, Compression::Zstd).unwrap();
+        assert!(decompress(&compressed, data.len()).is_ok());
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+    }
+
+    #[test]
+    fn test_lying_header() {
+        let data = vec![0u8; 100_000];
+        let mut compressed = compress(&data, Compression::Deflate).unwrap();
+        // claim a small output, the bound still stops at the limit
+        compressed[5..HEADER_LEN].copy_from_slice(&10u64.to_be_bytes());
+        assert!(decompress(&compressed, 1000).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_payload() {
+        let mut compressed = compress(&testing_data(), Compression::Brotli).unwrap();
+        compressed.truncate(compressed.len() / 2);
+        assert!(decompress(&compressed, DEFAULT_MAX_SIZE).is_err());
+        assert!(decompress(b"not compressed", DEFAULT_MAX_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Compression::from_str("zstd").unwrap(), Compression::Zstd);
+        assert!(Compression::from_str("lzma").is_err());
+        assert_eq!(Compression::Brotli.to_string(), "brotli");
+    }
+}
@@ -0,0 +1,393 @@
+/// sealing of hidden messages.
+/// passphrase envelopes derive the key with Argon2id, recipient envelopes use X25519,
+/// both seal the payload with ChaCha20-Poly1305.
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{Error, Result};
+
+/// magic bytes at the start of a passphrase envelope
+pub const PASSPHRASE_MAGIC: [u8; 4] = *b"PIEp";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+/// magic + m_cost + t_cost + p_cost + salt + nonce
+const HEADER_LEN: usize = 4 + 3 * 4 + SALT_LEN + NONCE_LEN;
+/// the most expensive kdf parameters an envelope may ask for, they are read before the tag is
+/// checked. 256 MiB of memory, 16 passes and 16 lanes are well above the defaults
+const MAX_M_COST: u32 = 256 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+/// check whether the data starts with a passphrase envelope header
+pub fn is_passphrase_sealed(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && data[0..4] == PASSPHRASE_MAGIC
+}
+
+/// encrypt the message, the returned bytes carry the kdf parameters, salt and nonce.
+pub fn seal_with_passphrase(message: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    seal_with_params(message, passphrase, Params::default())
+}
+
+fn seal_with_params(message: &[u8], passphrase: &str, params: Params) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let header = [
+        PASSPHRASE_MAGIC.as_ref(),
+        params.m_cost().to_be_bytes().as_ref(),
+        params.t_cost().to_be_bytes().as_ref(),
+        params.p_cost().to_be_bytes().as_ref(),
+        salt.as_ref(),
+        nonce.as_ref(),
+    ]
+    .concat();
+
+    let key = derive_key(passphrase, &salt, params)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    // the header is authenticated as well, so the kdf parameters can not be tampered with
+    let sealed = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: message,
+                aad: &header,
+            },
+        )
+        .map_err(|_| Error::from("encryption failed"))?;
+
+    Ok([header, sealed].concat())
+}
+
+/// decrypt an envelope produced by `seal_with_passphrase` and verify its integrity
+pub fn open_with_passphrase(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if !is_passphrase_sealed(data) {
+        return Err(Error::from("data is not a passphrase envelope"));
+    }
+    let be_u32 = |offset: usize| u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap());
+    let (m_cost, t_cost, p_cost) = (be_u32(4), be_u32(8), be_u32(12));
+    if m_cost > MAX_M_COST || t_cost > MAX_T_COST || p_cost > MAX_P_COST {
+        return Err(Error::from(format!(
+            "kdf parameters m={} t={} p={} are over the limit of m={} t={} p={}",
+            m_cost, t_cost, p_cost, MAX_M_COST, MAX_T_COST, MAX_P_COST
+        )));
+    }
+    let params = Params::new(m_cost, t_cost, p_cost, Some(KEY_LEN))
+        .map_err(|e| Error::from(format!("invalid kdf parameters: {}", e)))?;
+    let salt = &data[16..16 + SALT_LEN];
+    let nonce = &data[16 + SALT_LEN..HEADER_LEN];
+
+    let key = derive_key(passphrase, salt, params)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: &data[HEADER_LEN..],
+                aad: &data[..HEADER_LEN],
+            },
+        )
+        .map_err(|_| Error::from("wrong passphrase or the message has been modified"))
+}
+
+/// derive a deterministic seed from the passphrase, for uses that have nowhere to store a
+/// random salt. the context keeps seeds of different uses apart.
+pub fn derive_seed(passphrase: &str, context: &str) -> Result<[u8; KEY_LEN]> {
+    derive_key(
+        passphrase,
+        &Sha256::digest(context.as_bytes()),
+        Params::default(),
+    )
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: Params) -> Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::from(format!("key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+/// magic bytes at the start of a recipient envelope
+pub const RECIPIENT_MAGIC: [u8; 4] = *b"PIEr";
+
+const PUBLIC_KEY_PREFIX: &str = "png-info-public-key:";
+const SECRET_KEY_PREFIX: &str = "png-info-secret-key:";
+/// the wrapped file key and its tag
+const STANZA_LEN: usize = KEY_LEN + 16;
+const RECIPIENT_INFO: &[u8] = b"png_info x25519 file key";
+
+/// the secret half of a recipient key pair
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    pub fn generate() -> Identity {
+        Identity {
+            secret: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.secret)
+    }
+
+    /// text form written by `keygen`
+    pub fn to_key_string(&self) -> String {
+        format!(
+            "{} {}\n",
+            SECRET_KEY_PREFIX,
+            to_hex(&self.secret.to_bytes())
+        )
+    }
+
+    pub fn from_key_string(s: &str) -> Result<Identity> {
+        let bytes = parse_key(s, SECRET_KEY_PREFIX)?;
+        Ok(Identity {
+            secret: StaticSecret::from(bytes),
+        })
+    }
+}
+
+/// text form of a recipient public key
+pub fn public_key_to_string(key: &PublicKey) -> String {
+    format!("{} {}\n", PUBLIC_KEY_PREFIX, to_hex(key.as_bytes()))
+}
+
+pub fn public_key_from_string(s: &str) -> Result<PublicKey> {
+    Ok(PublicKey::from(parse_key(s, PUBLIC_KEY_PREFIX)?))
+}
+
+pub(crate) fn parse_key(s: &str, prefix: &str) -> Result<[u8; KEY_LEN]> {
+    let hex = s
+        .trim()
+        .strip_prefix(prefix)
+        .ok_or_else(|| Error::from(format!("key must start with `{}`", prefix)))?;
+    from_hex(hex.trim())?
+        .try_into()
+        .map_err(|_| Error::from("key must be 32 bytes long"))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(Error::from("invalid hex string"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(Error::from))
+        .collect()
+}
+
+/// check whether the data starts with a recipient envelope header
+pub fn is_recipient_sealed(data: &[u8]) -> bool {
+    data.len() >= 4 + KEY_LEN + 2 && data[0..4] == RECIPIENT_MAGIC
+}
+
+/// encrypt the message so that only the holders of the recipients' identities can read it.
+///
+/// layout: magic | ephemeral public key | recipient count (u16) | wrapped keys | nonce | ciphertext
+pub fn seal_for_recipients(message: &[u8], recipients: &[PublicKey]) -> Result<Vec<u8>> {
+    if recipients.is_empty() || recipients.len() > u16::MAX as usize {
+        return Err(Error::from("invalid number of recipients"));
+    }
+    let mut file_key = [0u8; KEY_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut file_key);
+    OsRng.fill_bytes(&mut nonce);
+
+    // a fresh key pair per envelope, shared by all recipients of this message
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let mut header = [
+        RECIPIENT_MAGIC.as_ref(),
+        ephemeral_public.as_bytes().as_ref(),
+        (recipients.len() as u16).to_be_bytes().as_ref(),
+    ]
+    .concat();
+    for recipient in recipients {
+        let shared_secret = ephemeral.diffie_hellman(recipient);
+        let wrap_key = wrapping_key(shared_secret.as_bytes(), &ephemeral_public, recipient);
+        let stanza = ChaCha20Poly1305::new(Key::from_slice(&wrap_key))
+            .encrypt(Nonce::from_slice(&[0u8; NONCE_LEN]), file_key.as_ref())
+            .map_err(|_| Error::from("encryption failed"))?;
+        header.extend_from_slice(&stanza);
+    }
+    header.extend_from_slice(&nonce);
+
+    let sealed = ChaCha20Poly1305::new(Key::from_slice(&file_key))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: message,
+                aad: &header,
+            },
+        )
+        .map_err(|_| Error::from("encryption failed"))?;
+
+    Ok([header, sealed].concat())
+}
+
+/// decrypt a recipient envelope with one of the identities it was sealed for
+pub fn open_with_identity(data: &[u8], identity: &Identity) -> Result<Vec<u8>> {
+    if !is_recipient_sealed(data) {
+        return Err(Error::from("data is not a recipient envelope"));
+    }
+    let ephemeral_public: [u8; KEY_LEN] = data[4..4 + KEY_LEN].try_into().unwrap();
+    let ephemeral_public = PublicKey::from(ephemeral_public);
+    let count = u16::from_be_bytes(data[4 + KEY_LEN..6 + KEY_LEN].try_into().unwrap()) as usize;
+    let stanzas_start = 6 + KEY_LEN;
+    let header_len = stanzas_start + count * STANZA_LEN + NONCE_LEN;
+    if data.len() < header_len {
+        return Err(Error::from("recipient envelope is truncated"));
+    }
+
+    let shared_secret = identity.secret.diffie_hellman(&ephemeral_public);
+    let wrap_key = wrapping_key(
+        shared_secret.as_bytes(),
+        &ephemeral_public,
+        &identity.public_key(),
+    );
+    let unwrap = ChaCha20Poly1305::new(Key::from_slice(&wrap_key));
+    let file_key = data[stanzas_start..header_len - NONCE_LEN]
+        .chunks(STANZA_LEN)
+        .find_map(|stanza| {
+            unwrap
+                .decrypt(Nonce::from_slice(&[0u8; NONCE_LEN]), stanza)
+                .ok()
+        })
+        .ok_or_else(|| Error::from("the message was not sealed for this identity"))?;
+
+    ChaCha20Poly1305::new(Key::from_slice(&file_key))
+        .decrypt(
+            Nonce::from_slice(&data[header_len - NONCE_LEN..header_len]),
+            Payload {
+                msg: &data[header_len..],
+                aad: &data[..header_len],
+            },
+        )
+        .map_err(|_| Error::from("the message has been modified"))
+}
+
+fn wrapping_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> [u8; KEY_LEN] {
+    let salt = [ephemeral.as_bytes().as_ref(), recipient.as_bytes().as_ref()].concat();
+    let mut key = [0u8; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(RECIPIENT_INFO, &mut key)
+        .unwrap();
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// cheap parameters so the tests stay fast
+    fn testing_params() -> Params {
+        Params::new(64, 1, 1, Some(KEY_LEN)).unwrap()
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let sealed = seal_with_params(b"secret message", "hunter2", testing_params()).unwrap();
+        assert!(is_passphrase_sealed(&sealed));
+        assert_eq!(sealed.len(), HEADER_LEN + b"secret message".len() + 16);
+        let opened = open_with_passphrase(&sealed, "hunter2").unwrap();
+        assert_eq!(opened, b"secret message");
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let sealed = seal_with_params(b"secret message", "hunter2", testing_params()).unwrap();
+        assert!(open_with_passphrase(&sealed, "hunter3").is_err());
+    }
+
+    #[test]
+    fn test_tampered_envelope() {
+        let mut sealed = seal_with_params(b"secret message", "hunter2", testing_params()).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open_with_passphrase(&sealed, "hunter2").is_err());
+
+        // changing the kdf parameters must be detected too
+        let mut sealed = seal_with_params(b"secret message", "hunter2", testing_params()).unwrap();
+        sealed[7] = 65;
+        assert!(open_with_passphrase(&sealed, "hunter2").is_err());
+    }
+
+    #[test]
+    fn test_expensive_params_are_refused() {
+        let sealed = seal_with_params(b"secret message", "hunter2", testing_params()).unwrap();
+        for offset in [4, 8, 12] {
+            let mut forged = sealed.clone();
+            forged[offset..offset + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+            let error = open_with_passphrase(&forged, "hunter2").unwrap_err();
+            assert!(error.to_string().contains("over the limit"), "{}", error);
+        }
+        assert!(Params::default().m_cost() <= MAX_M_COST);
+        assert!(Params::default().t_cost() <= MAX_T_COST);
+        assert!(Params::default().p_cost() <= MAX_P_COST);
+    }
+
+    #[test]
+    fn test_plain_data_is_not_sealed() {
+        assert!(!is_passphrase_sealed(
+            b"This is where your secret message will be!"
+        ));
+        assert!(open_with_passphrase(b"plain", "hunter2").is_err());
+    }
+
+    #[test]
+    fn test_seal_for_recipients() {
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let eve = Identity::generate();
+        let sealed =
+            seal_for_recipients(b"handoff", &[alice.public_key(), bob.public_key()]).unwrap();
+        assert!(is_recipient_sealed(&sealed));
+        assert!(!is_passphrase_sealed(&sealed));
+
+        assert_eq!(open_with_identity(&sealed, &alice).unwrap(), b"handoff");
+        assert_eq!(open_with_identity(&sealed, &bob).unwrap(), b"handoff");
+        assert!(open_with_identity(&sealed, &eve).is_err());
+    }
+
+    #[test]
+    fn test_tampered_recipient_envelope() {
+        let alice = Identity::generate();
+        let mut sealed = seal_for_recipients(b"handoff", &[alice.public_key()]).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open_with_identity(&sealed, &alice).is_err());
+    }
+
+    #[test]
+    fn test_key_strings() {
+        let identity = Identity::generate();
+        let restored = Identity::from_key_string(&identity.to_key_string()).unwrap();
+        assert_eq!(restored.public_key(), identity.public_key());
+
+        let public = public_key_to_string(&identity.public_key());
+        assert_eq!(
+            public_key_from_string(&public).unwrap(),
+            identity.public_key()
+        );
+        assert!(public_key_from_string(&identity.to_key_string()).is_err());
+        assert!(Identity::from_key_string("png-info-secret-key: abcd").is_err());
+    }
+}
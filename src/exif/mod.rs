@@ -0,0 +1,565 @@
+/// the eXIf chunk: a TIFF structure of image file directories holding the EXIF tags.
+/// IFD0 points to the EXIF and GPS sub-IFDs and is followed by IFD1 for the thumbnail.
+/// edits patch the bytes in place, so the offsets inside maker notes stay valid.
+use std::{collections::BTreeMap, fmt};
+
+use crate::{png::Png, Error, Result};
+
+/// IFD0 tag pointing to the EXIF IFD
+const EXIF_POINTER: u16 = 0x8769;
+/// IFD0 tag pointing to the GPS IFD
+const GPS_POINTER: u16 = 0x8825;
+/// EXIF tag pointing to the interoperability IFD
+const INTEROP_POINTER: u16 = 0xa005;
+
+/// more entries than this in one IFD means the data is garbage
+const MAX_ENTRIES: usize = 1024;
+
+/// values longer than this are summarized when printed
+const MAX_PRINTED_VALUES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// `II`
+    Little,
+    /// `MM`
+    Big,
+}
+
+/// the directory a tag was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Ifd {
+    Ifd0,
+    Exif,
+    Gps,
+    Interop,
+    /// the thumbnail
+    Ifd1,
+}
+
+impl fmt::Display for Ifd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Ifd::Ifd0 => "IFD0",
+            Ifd::Exif => "EXIF",
+            Ifd::Gps => "GPS",
+            Ifd::Interop => "Interop",
+            Ifd::Ifd1 => "IFD1",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl Value {
+    /// the first value as a number, rationals divided out
+    pub fn as_f64(&self, index: usize) -> Option<f64> {
+        match self {
+            Value::Byte(values) | Value::Undefined(values) => values.get(index).map(|v| *v as f64),
+            Value::Short(values) => values.get(index).map(|v| *v as f64),
+            Value::Long(values) => values.get(index).map(|v| *v as f64),
+            Value::Rational(values) => values
+                .get(index)
+                .filter(|(_, d)| *d != 0)
+                .map(|(n, d)| *n as f64 / *d as f64),
+            Value::SByte(values) => values.get(index).map(|v| *v as f64),
+            Value::SShort(values) => values.get(index).map(|v| *v as f64),
+            Value::SLong(values) => values.get(index).map(|v| *v as f64),
+            Value::SRational(values) => values
+                .get(index)
+                .filter(|(_, d)| *d != 0)
+                .map(|(n, d)| *n as f64 / *d as f64),
+            Value::Float(values) => values.get(index).map(|v| *v as f64),
+            Value::Double(values) => values.get(index).copied(),
+            Value::Ascii(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T: fmt::Display>(values: &[T]) -> String {
+            if values.len() > MAX_PRINTED_VALUES {
+                return format!("{} values", values.len());
+            }
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        let fractions = |values: Vec<String>| list(&values);
+        match self {
+            Value::Ascii(text) => write!(f, "\"{}\"", text),
+            Value::Byte(values) => write!(f, "{}", list(values)),
+            Value::Undefined(values) => write!(f, "{} bytes", values.len()),
+            Value::Short(values) => write!(f, "{}", list(values)),
+            Value::Long(values) => write!(f, "{}", list(values)),
+            Value::Rational(values) => write!(
+                f,
+                "{}",
+                fractions(values.iter().map(|(n, d)| format!("{}/{}", n, d)).collect())
+            ),
+            Value::SByte(values) => write!(f, "{}", list(values)),
+            Value::SShort(values) => write!(f, "{}", list(values)),
+            Value::SLong(values) => write!(f, "{}", list(values)),
+            Value::SRational(values) => write!(
+                f,
+                "{}",
+                fractions(values.iter().map(|(n, d)| format!("{}/{}", n, d)).collect())
+            ),
+            Value::Float(values) => write!(f, "{}", list(values)),
+            Value::Double(values) => write!(f, "{}", list(values)),
+        }
+    }
+}
+
+/// the name of the common tags
+pub fn tag_name(ifd: Ifd, tag: u16) -> Option<&'static str> {
+    let name = match (ifd, tag) {
+        (Ifd::Gps, 0x0000) => "GPSVersionID",
+        (Ifd::Gps, 0x0001) => "GPSLatitudeRef",
+        (Ifd::Gps, 0x0002) => "GPSLatitude",
+        (Ifd::Gps, 0x0003) => "GPSLongitudeRef",
+        (Ifd::Gps, 0x0004) => "GPSLongitude",
+        (Ifd::Gps, 0x0005) => "GPSAltitudeRef",
+        (Ifd::Gps, 0x0006) => "GPSAltitude",
+        (Ifd::Gps, 0x0007) => "GPSTimeStamp",
+        (Ifd::Gps, 0x001d) => "GPSDateStamp",
+        (Ifd::Gps, _) => return None,
+        (Ifd::Interop, 0x0001) => "InteropIndex",
+        (Ifd::Interop, _) => return None,
+        (_, 0x010e) => "ImageDescription",
+        (_, 0x010f) => "Make",
+        (_, 0x0110) => "Model",
+        (_, 0x0112) => "Orientation",
+        (_, 0x011a) => "XResolution",
+        (_, 0x011b) => "YResolution",
+        (_, 0x0128) => "ResolutionUnit",
+        (_, 0x0131) => "Software",
+        (_, 0x0132) => "DateTime",
+        (_, 0x013b) => "Artist",
+        (_, 0x0201) => "ThumbnailOffset",
+        (_, 0x0202) => "ThumbnailLength",
+        (_, 0x8298) => "Copyright",
+        (_, 0x829a) => "ExposureTime",
+        (_, 0x829d) => "FNumber",
+        (_, EXIF_POINTER) => "ExifIFD",
+        (_, GPS_POINTER) => "GPSInfo",
+        (_, 0x8827) => "ISO",
+        (_, 0x9000) => "ExifVersion",
+        (_, 0x9003) => "DateTimeOriginal",
+        (_, 0x9004) => "DateTimeDigitized",
+        (_, 0x9010) => "OffsetTime",
+        (_, 0x920a) => "FocalLength",
+        (_, 0x927c) => "MakerNote",
+        (_, 0x9286) => "UserComment",
+        (_, 0xa002) => "PixelXDimension",
+        (_, 0xa003) => "PixelYDimension",
+        (_, INTEROP_POINTER) => "InteropIFD",
+        (_, 0xa433) => "LensMake",
+        (_, 0xa434) => "LensModel",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// bounds checked reads in the byte order of the TIFF header
+struct Reader<'a> {
+    data: &'a [u8],
+    order: ByteOrder,
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .map(|end| &self.data[offset..end])
+            .ok_or_else(|| Error::from(format!("eXIf is truncated at byte {}", offset)))
+    }
+
+    fn array<const N: usize>(&self, offset: usize) -> Result<[u8; N]> {
+        let mut bytes: [u8; N] = self.slice(offset, N)?.try_into().unwrap();
+        if self.order == ByteOrder::Little {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.array(offset)?))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.array(offset)?))
+    }
+
+    fn write_u16(&self, data: &mut [u8], offset: usize, value: u16) {
+        let bytes = match self.order {
+            ByteOrder::Little => value.to_le_bytes(),
+            ByteOrder::Big => value.to_be_bytes(),
+        };
+        data[offset..offset + 2].copy_from_slice(&bytes);
+    }
+
+    /// where the value of the entry at `entry` is stored and how many bytes it takes,
+    /// `None` for unknown types
+    fn value_location(&self, entry: usize) -> Result<Option<(usize, usize)>> {
+        let size = match self.u16(entry + 2)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return Ok(None),
+        };
+        let len = (self.u32(entry + 4)? as usize)
+            .checked_mul(size)
+            .ok_or_else(|| Error::from("eXIf value is too long"))?;
+        let offset = if len <= 4 {
+            entry + 8
+        } else {
+            self.u32(entry + 8)? as usize
+        };
+        self.slice(offset, len)?;
+        Ok(Some((offset, len)))
+    }
+
+    fn value(&self, entry: usize) -> Result<Option<Value>> {
+        let Some((offset, len)) = self.value_location(entry)? else {
+            return Ok(None);
+        };
+        let bytes = self.slice(offset, len)?;
+        let each = |size: usize| (0..len / size).map(move |i| offset + i * size);
+        let value = match self.u16(entry + 2)? {
+            1 => Value::Byte(bytes.to_vec()),
+            2 => Value::Ascii(
+                bytes
+                    .split(|byte| *byte == 0)
+                    .next()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|byte| *byte as char)
+                    .collect(),
+            ),
+            3 => Value::Short(each(2).map(|at| self.u16(at)).collect::<Result<_>>()?),
+            4 => Value::Long(each(4).map(|at| self.u32(at)).collect::<Result<_>>()?),
+            5 => Value::Rational(
+                each(8)
+                    .map(|at| Ok((self.u32(at)?, self.u32(at + 4)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            6 => Value::SByte(bytes.iter().map(|byte| *byte as i8).collect()),
+            7 => Value::Undefined(bytes.to_vec()),
+            8 => Value::SShort(
+                each(2)
+                    .map(|at| Ok(self.u16(at)? as i16))
+                    .collect::<Result<_>>()?,
+            ),
+            9 => Value::SLong(
+                each(4)
+                    .map(|at| Ok(self.u32(at)? as i32))
+                    .collect::<Result<_>>()?,
+            ),
+            10 => Value::SRational(
+                each(8)
+                    .map(|at| Ok((self.u32(at)? as i32, self.u32(at + 4)? as i32)))
+                    .collect::<Result<_>>()?,
+            ),
+            11 => Value::Float(
+                each(4)
+                    .map(|at| Ok(f32::from_bits(self.u32(at)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            _ => Value::Double(
+                each(8)
+                    .map(|at| Ok(f64::from_bits(u64::from_be_bytes(self.array(at)?))))
+                    .collect::<Result<_>>()?,
+            ),
+        };
+        Ok(Some(value))
+    }
+
+    /// the offsets of the entries of the IFD at `offset` and the offset of the next IFD
+    fn entries(&self, offset: usize) -> Result<(Vec<usize>, usize)> {
+        let count = self.u16(offset)? as usize;
+        if count > MAX_ENTRIES {
+            return Err(Error::from(format!(
+                "eXIf IFD at byte {} claims {} entries",
+                offset, count
+            )));
+        }
+        let entries: Vec<usize> = (0..count).map(|i| offset + 2 + i * 12).collect();
+        let next = self.u32(offset + 2 + count * 12)? as usize;
+        Ok((entries, next))
+    }
+
+    /// the offset of IFD0 after checking the header
+    fn first_ifd(&self) -> Result<usize> {
+        if self.u16(2)? != 42 {
+            return Err(Error::from("eXIf does not hold a TIFF header"));
+        }
+        Ok(self.u32(4)? as usize)
+    }
+
+    fn new(data: &'a [u8]) -> Result<Reader<'a>> {
+        let order = match data.get(0..2) {
+            Some(b"II") => ByteOrder::Little,
+            Some(b"MM") => ByteOrder::Big,
+            _ => {
+                return Err(Error::from(
+                    "eXIf does not start with a TIFF byte order mark",
+                ))
+            }
+        };
+        Ok(Reader { data, order })
+    }
+}
+
+/// the tags of an eXIf chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exif {
+    pub byte_order: ByteOrder,
+    pub tags: BTreeMap<(Ifd, u16), Value>,
+}
+
+impl Exif {
+    pub fn parse(data: &[u8]) -> Result<Exif> {
+        let reader = Reader::new(data)?;
+        let mut tags = BTreeMap::new();
+        let mut pending = vec![(Ifd::Ifd0, reader.first_ifd()?)];
+        let mut visited = Vec::new();
+        while let Some((ifd, offset)) = pending.pop() {
+            // offset 0 ends the IFD chain, a repeated one would loop forever
+            if offset == 0 || visited.contains(&offset) {
+                continue;
+            }
+            visited.push(offset);
+            let (entries, next) = reader.entries(offset)?;
+            for entry in entries {
+                let tag = reader.u16(entry)?;
+                let Some(value) = reader.value(entry)? else {
+                    continue;
+                };
+                let sub_ifd = match (ifd, tag) {
+                    (Ifd::Ifd0, EXIF_POINTER) => Some(Ifd::Exif),
+                    (Ifd::Ifd0, GPS_POINTER) => Some(Ifd::Gps),
+                    (Ifd::Exif, INTEROP_POINTER) => Some(Ifd::Interop),
+                    _ => None,
+                };
+                if let (Some(sub_ifd), Some(pointer)) = (sub_ifd, value.as_f64(0)) {
+                    pending.push((sub_ifd, pointer as usize));
+                }
+                tags.insert((ifd, tag), value);
+            }
+            if ifd == Ifd::Ifd0 {
+                pending.push((Ifd::Ifd1, next));
+            }
+        }
+        Ok(Exif {
+            byte_order: reader.order,
+            tags,
+        })
+    }
+
+    pub fn from_png(png: &Png) -> Result<Option<Exif>> {
+        png.chunk_by_type("eXIf")
+            .map(|chunk| Exif::parse(chunk.data()))
+            .transpose()
+    }
+
+    pub fn has_gps(&self) -> bool {
+        self.tags.keys().any(|(ifd, _)| *ifd == Ifd::Gps)
+    }
+
+    /// latitude and longitude in degrees, south and west negative
+    pub fn gps_position(&self) -> Option<(f64, f64)> {
+        let degrees = |tag: u16, reference: u16, negative: &str| -> Option<f64> {
+            let value = self.tags.get(&(Ifd::Gps, tag))?;
+            let degrees = value.as_f64(0)? + value.as_f64(1)? / 60.0 + value.as_f64(2)? / 3600.0;
+            match self.tags.get(&(Ifd::Gps, reference)) {
+                Some(Value::Ascii(text)) if text == negative => Some(-degrees),
+                _ => Some(degrees),
+            }
+        };
+        Some((degrees(2, 1, "S")?, degrees(4, 3, "W")?))
+    }
+}
+
+impl fmt::Display for Exif {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ((ifd, tag), value) in &self.tags {
+            let name = tag_name(*ifd, *tag)
+                .map(String::from)
+                .unwrap_or_else(|| format!("0x{:04x}", tag));
+            writeln!(f, "{} {}: {}", ifd, name, value)?;
+        }
+        if let Some((latitude, longitude)) = self.gps_position() {
+            writeln!(f, "GPS position: {:.6}, {:.6}", latitude, longitude)?;
+        }
+        Ok(())
+    }
+}
+
+/// drop the GPS pointer from IFD0 and zero the GPS IFD with its values.
+/// returns `None` when there is no GPS IFD.
+pub fn remove_gps(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let reader = Reader::new(data)?;
+    let ifd0 = reader.first_ifd()?;
+    let (entries, _) = reader.entries(ifd0)?;
+    let Some(index) = entries
+        .iter()
+        .position(|entry| reader.u16(*entry).is_ok_and(|tag| tag == GPS_POINTER))
+    else {
+        return Ok(None);
+    };
+    let gps = reader.u32(entries[index] + 8)? as usize;
+
+    let mut blank = Vec::new();
+    if gps != 0 {
+        let (gps_entries, _) = reader.entries(gps)?;
+        for entry in &gps_entries {
+            if let Some((offset, len)) = reader.value_location(*entry)? {
+                blank.push((offset, len));
+            }
+        }
+        blank.push((gps, 2 + gps_entries.len() * 12 + 4));
+    }
+
+    let mut output = data.to_vec();
+    // shift the later entries and the next IFD offset over the GPS pointer
+    let end = ifd0 + 2 + entries.len() * 12 + 4;
+    output.copy_within(entries[index] + 12..end, entries[index]);
+    output[end - 12..end].fill(0);
+    reader.write_u16(&mut output, ifd0, (entries.len() - 1) as u16);
+    for (offset, len) in blank {
+        output[offset..offset + len].fill(0);
+    }
+    Ok(Some(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IFD0 with Make, Orientation and the EXIF and GPS pointers, EXIF with
+    /// DateTimeOriginal, GPS with 48°51'29.6" N 2°17'40.2" E
+    fn testing_exif(order: ByteOrder) -> Vec<u8> {
+        let u16 = |value: u16| match order {
+            ByteOrder::Little => value.to_le_bytes().to_vec(),
+            ByteOrder::Big => value.to_be_bytes().to_vec(),
+        };
+        let u32 = |value: u32| match order {
+            ByteOrder::Little => value.to_le_bytes().to_vec(),
+            ByteOrder::Big => value.to_be_bytes().to_vec(),
+        };
+        let entry = |tag: u16, kind: u16, count: u32, value: Vec<u8>| {
+            [u16(tag), u16(kind), u32(count), value].concat()
+        };
+        let rationals = |values: [(u32, u32); 3]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|(n, d)| [u32(*n), u32(*d)].concat())
+                .collect()
+        };
+        [
+            match order {
+                ByteOrder::Little => b"II".to_vec(),
+                ByteOrder::Big => b"MM".to_vec(),
+            },
+            u16(42),
+            u32(8),
+            // IFD0 at 8, 4 entries, its values from 62
+            u16(4),
+            entry(0x010f, 2, 6, u32(62)),
+            entry(0x0112, 3, 1, [u16(1), u16(0)].concat()),
+            entry(EXIF_POINTER, 4, 1, u32(68)),
+            entry(GPS_POINTER, 4, 1, u32(106)),
+            u32(0),
+            b"Canon\0".to_vec(),
+            // EXIF IFD at 68, its value from 86
+            u16(1),
+            entry(0x9003, 2, 20, u32(86)),
+            u32(0),
+            b"2024:01:02 03:04:05\0".to_vec(),
+            // GPS IFD at 106, its values from 160
+            u16(4),
+            entry(1, 2, 2, b"N\0\0\0".to_vec()),
+            entry(2, 5, 3, u32(160)),
+            entry(3, 2, 2, b"E\0\0\0".to_vec()),
+            entry(4, 5, 3, u32(184)),
+            u32(0),
+            rationals([(48, 1), (51, 1), (296, 10)]),
+            rationals([(2, 1), (17, 1), (402, 10)]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_parse() {
+        for order in [ByteOrder::Little, ByteOrder::Big] {
+            let exif = Exif::parse(&testing_exif(order)).unwrap();
+            assert_eq!(exif.byte_order, order);
+            assert_eq!(
+                exif.tags[&(Ifd::Ifd0, 0x010f)],
+                Value::Ascii(String::from("Canon"))
+            );
+            assert_eq!(exif.tags[&(Ifd::Ifd0, 0x0112)], Value::Short(vec![1]));
+            assert_eq!(
+                exif.tags[&(Ifd::Exif, 0x9003)],
+                Value::Ascii(String::from("2024:01:02 03:04:05"))
+            );
+            assert!(exif.has_gps());
+            let (latitude, longitude) = exif.gps_position().unwrap();
+            assert!((latitude - 48.858222).abs() < 1e-5);
+            assert!((longitude - 2.294500).abs() < 1e-5);
+            assert!(exif
+                .to_string()
+                .contains("IFD0 Make: \"Canon\"\nIFD0 Orientation: 1\n"));
+        }
+    }
+
+    #[test]
+    fn test_remove_gps() {
+        for order in [ByteOrder::Little, ByteOrder::Big] {
+            let data = testing_exif(order);
+            let removed = remove_gps(&data).unwrap().unwrap();
+            assert_eq!(removed.len(), data.len());
+            // the coordinates are gone from the bytes, not only unreferenced
+            assert!(removed[106..].iter().all(|byte| *byte == 0));
+
+            let exif = Exif::parse(&removed).unwrap();
+            assert!(!exif.has_gps());
+            assert!(!exif.tags.contains_key(&(Ifd::Ifd0, GPS_POINTER)));
+            assert_eq!(exif.tags.len(), 4);
+            assert_eq!(remove_gps(&removed).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        let data = testing_exif(ByteOrder::Big);
+        assert!(Exif::parse(&data[..60]).is_err());
+        assert!(Exif::parse(b"XX\0*\0\0\0\x08").is_err());
+
+        // IFD1 pointing back at IFD0 must not loop
+        let mut looped = data.clone();
+        looped[58..62].copy_from_slice(&8u32.to_be_bytes());
+        assert_eq!(Exif::parse(&looped).unwrap().tags.len(), 9);
+    }
+}
@@ -0,0 +1,931 @@
+/// pixel data of a png: the IHDR header and the unfiltered scanlines of IDAT.
+/// [introduction](http://www.libpng.org/pub/png/spec/1.2/PNG-Filters.html)
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
+use std::{
+    fmt,
+    io::{Read, Write},
+};
+
+use crate::{png::Png, Error, Result};
+
+pub mod zlib;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn code(self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Result<ColorType> {
+        match code {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(Error::from(format!("invalid color type {}", code))),
+        }
+    }
+
+    pub fn channels(self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    pub fn has_alpha(self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+
+    pub fn is_grayscale(self) -> bool {
+        matches!(self, ColorType::Grayscale | ColorType::GrayscaleAlpha)
+    }
+
+    fn allowed_bit_depths(self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            _ => &[8, 16],
+        }
+    }
+}
+
+/// the IHDR chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    /// Adam7 interlacing
+    pub interlaced: bool,
+}
+
+/// x offset, y offset, x step, y step of the seven Adam7 passes
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+impl Header {
+    pub fn from_png(png: &Png) -> Result<Header> {
+        let chunk = png
+            .chunk_by_type("IHDR")
+            .ok_or_else(|| Error::from("missing IHDR chunk"))?;
+        Header::try_from(chunk.data())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            self.width.to_be_bytes().as_ref(),
+            self.height.to_be_bytes().as_ref(),
+            &[
+                self.bit_depth,
+                self.color_type.code(),
+                0,
+                0,
+                self.interlaced as u8,
+            ],
+        ]
+        .concat()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.color_type.channels()
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// distance in bytes to the corresponding byte of the previous pixel, used by the filters
+    pub fn filter_stride(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// bytes of one scanline of the given width, without the filter type byte. `raw_len` checks
+    /// that the scanlines of the image fit in memory sizes
+    pub fn row_len(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// size of the sub images stored in IDAT: seven Adam7 passes, or the whole image
+    pub fn pass_sizes(&self) -> Vec<(u32, u32)> {
+        if !self.interlaced {
+            return vec![(self.width, self.height)];
+        }
+        ADAM7
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                let size = |total: u32, start: u32, step: u32| {
+                    if total > start {
+                        (total - start).div_ceil(step)
+                    } else {
+                        0
+                    }
+                };
+                (size(self.width, x0, dx), size(self.height, y0, dy))
+            })
+            .collect()
+    }
+
+    /// position in the full image of pixel `(x, y)` of a pass
+    pub fn pass_to_image(&self, pass: usize, x: u32, y: u32) -> (u32, u32) {
+        if !self.interlaced {
+            return (x, y);
+        }
+        let (x0, y0, dx, dy) = ADAM7[pass];
+        (x0 + x * dx, y0 + y * dy)
+    }
+
+    /// length of the inflated IDAT stream: every scanline with its filter type byte. a valid IHDR
+    /// of 2^31 - 1 by 2^31 - 1 pixels can exceed usize
+    pub fn raw_len(&self) -> Result<usize> {
+        self.pass_sizes()
+            .iter()
+            .filter(|(width, height)| *width > 0 && *height > 0)
+            .try_fold(0usize, |total, &(width, height)| {
+                (width as usize)
+                    .checked_mul(self.bits_per_pixel())
+                    .map(|bits| bits.div_ceil(8))
+                    .and_then(|row_len| row_len.checked_add(1))
+                    .and_then(|line| line.checked_mul(height as usize))
+                    .and_then(|pass| total.checked_add(pass))
+            })
+            .ok_or_else(|| Error::from("IHDR size overflows"))
+    }
+}
+
+impl TryFrom<&[u8]> for Header {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() != 13 {
+            return Err(Error::from("IHDR must be 13 bytes long"));
+        }
+        let width = u32::from_be_bytes(value[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(value[4..8].try_into().unwrap());
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+            return Err(Error::from(format!(
+                "invalid image size {}x{}",
+                width, height
+            )));
+        }
+        let bit_depth = value[8];
+        let color_type = ColorType::from_code(value[9])?;
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(Error::from(format!(
+                "bit depth {} is not allowed for color type {}",
+                bit_depth,
+                color_type.code()
+            )));
+        }
+        if value[10] != 0 || value[11] != 0 {
+            return Err(Error::from("unknown compression or filter method"));
+        }
+        let interlaced = match value[12] {
+            0 => false,
+            1 => true,
+            other => return Err(Error::from(format!("invalid interlace method {}", other))),
+        };
+        Ok(Header {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced,
+        })
+    }
+}
+
+/// the tRNS chunk, its layout depends on the color type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transparency {
+    /// alpha of the first palette entries, missing entries are opaque
+    PaletteAlpha(Vec<u8>),
+    /// the gray value of fully transparent pixels
+    GrayKey(u16),
+    /// the color of fully transparent pixels
+    RgbKey(u16, u16, u16),
+}
+
+impl Transparency {
+    pub fn parse(data: &[u8], color_type: ColorType) -> Result<Transparency> {
+        let be_u16 = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        match (color_type, data.len()) {
+            (ColorType::Indexed, len) if len <= 256 => {
+                Ok(Transparency::PaletteAlpha(data.to_vec()))
+            }
+            (ColorType::Grayscale, 2) => Ok(Transparency::GrayKey(be_u16(0))),
+            (ColorType::Rgb, 6) => Ok(Transparency::RgbKey(be_u16(0), be_u16(2), be_u16(4))),
+            (ColorType::GrayscaleAlpha | ColorType::Rgba, _) => Err(Error::from(
+                "tRNS is not allowed for color types with an alpha channel",
+            )),
+            (_, len) => Err(Error::from(format!(
+                "tRNS of {} bytes does not match color type {}",
+                len,
+                color_type.code()
+            ))),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Transparency::PaletteAlpha(alpha) => alpha.clone(),
+            Transparency::GrayKey(gray) => gray.to_be_bytes().to_vec(),
+            Transparency::RgbKey(r, g, b) => {
+                [r.to_be_bytes(), g.to_be_bytes(), b.to_be_bytes()].concat()
+            }
+        }
+    }
+
+    pub fn from_png(png: &Png, color_type: ColorType) -> Result<Option<Transparency>> {
+        png.chunk_by_type("tRNS")
+            .map(|chunk| Transparency::parse(chunk.data(), color_type))
+            .transpose()
+    }
+}
+
+impl fmt::Display for Transparency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transparency::PaletteAlpha(alpha) => {
+                write!(f, "palette alpha for {} entries", alpha.len())
+            }
+            Transparency::GrayKey(gray) => write!(f, "gray key {}", gray),
+            Transparency::RgbKey(r, g, b) => write!(f, "RGB key ({}, {}, {})", r, g, b),
+        }
+    }
+}
+
+/// the unfiltered scanlines of one pass, back to back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// decoded pixel data, one sub image per Adam7 pass or a single one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageData {
+    pub header: Header,
+    pub passes: Vec<SubImage>,
+    pub transparency: Option<Transparency>,
+}
+
+/// inflate the concatenated IDAT chunks, refusing to produce more than `limit` bytes
+pub fn inflate_idat(png: &Png, limit: usize) -> Result<Vec<u8>> {
+    let compressed = png.idat_stream();
+    if compressed.is_empty() {
+        return Err(Error::from("missing IDAT chunk"));
+    }
+    inflate(&compressed, limit).map_err(|e| Error::from(format!("IDAT {}", e)))
+}
+
+/// inflate a zlib stream, refusing to produce more than `limit` bytes
+pub fn inflate(compressed: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut raw = Vec::new();
+    ZlibDecoder::new(compressed)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut raw)
+        .map_err(|e| Error::from(format!("inflate failed: {}", e)))?;
+    Ok(raw)
+}
+
+impl ImageData {
+    /// inflate and unfilter the IDAT stream of the png
+    pub fn decode(png: &Png) -> Result<ImageData> {
+        let header = Header::from_png(png)?;
+        let raw = inflate_idat(png, header.raw_len()?)?;
+        ImageData::from_raw(
+            header,
+            &raw,
+            Transparency::from_png(png, header.color_type)?,
+        )
+        .map_err(|e| Error::from(format!("IDAT {}", e)))
+    }
+
+    /// inflate and unfilter a zlib stream of filtered scanlines laid out as `header` says,
+    /// like the data of an APNG frame
+    pub fn decode_stream(
+        header: Header,
+        compressed: &[u8],
+        transparency: Option<Transparency>,
+    ) -> Result<ImageData> {
+        let raw = inflate(compressed, header.raw_len()?)?;
+        ImageData::from_raw(header, &raw, transparency)
+    }
+
+    /// unfilter the inflated scanlines
+    fn from_raw(
+        header: Header,
+        raw: &[u8],
+        transparency: Option<Transparency>,
+    ) -> Result<ImageData> {
+        let raw_len = header.raw_len()?;
+        if raw.len() != raw_len {
+            return Err(Error::from(format!(
+                "holds {} bytes, {} expected",
+                raw.len(),
+                raw_len
+            )));
+        }
+
+        let stride = header.filter_stride();
+        let mut pivot = 0;
+        let mut passes = Vec::new();
+        for (width, height) in header.pass_sizes() {
+            if width == 0 || height == 0 {
+                passes.push(SubImage {
+                    width,
+                    height,
+                    data: Vec::new(),
+                });
+                continue;
+            }
+            let row_len = header.row_len(width);
+            let mut data = vec![0u8; row_len * height as usize];
+            for y in 0..height as usize {
+                let filter = raw[pivot];
+                let line = &raw[pivot + 1..pivot + 1 + row_len];
+                pivot += 1 + row_len;
+                let (previous, current) = data.split_at_mut(y * row_len);
+                let previous = if y == 0 {
+                    None
+                } else {
+                    Some(&previous[(y - 1) * row_len..])
+                };
+                unfilter(filter, line, previous, &mut current[..row_len], stride)?;
+            }
+            passes.push(SubImage {
+                width,
+                height,
+                data,
+            });
+        }
+        Ok(ImageData {
+            header,
+            passes,
+            transparency,
+        })
+    }
+
+    /// the samples of every pixel in row order, whatever the interlacing
+    pub fn samples(&self) -> Vec<u16> {
+        let header = &self.header;
+        let channels = header.channels();
+        let mut samples = vec![0u16; header.width as usize * header.height as usize * channels];
+        for (pass, sub) in self.passes.iter().enumerate() {
+            let row_len = header.row_len(sub.width);
+            for y in 0..sub.height {
+                let row = &sub.data[y as usize * row_len..(y as usize + 1) * row_len];
+                for x in 0..sub.width {
+                    let (ix, iy) = header.pass_to_image(pass, x, y);
+                    let at = (iy * header.width + ix) as usize * channels;
+                    for c in 0..channels {
+                        samples[at + c] =
+                            get_sample(row, x as usize * channels + c, header.bit_depth);
+                    }
+                }
+            }
+        }
+        samples
+    }
+
+    /// the reverse of `samples`, laid out in passes when the header is interlaced
+    pub fn from_samples(
+        header: Header,
+        samples: &[u16],
+        transparency: Option<Transparency>,
+    ) -> ImageData {
+        let channels = header.channels();
+        let passes = header
+            .pass_sizes()
+            .into_iter()
+            .enumerate()
+            .map(|(pass, (width, height))| {
+                let row_len = header.row_len(width);
+                let mut data = vec![0u8; row_len * height as usize];
+                for y in 0..height {
+                    let row = &mut data[y as usize * row_len..(y as usize + 1) * row_len];
+                    for x in 0..width {
+                        let (ix, iy) = header.pass_to_image(pass, x, y);
+                        let at = (iy * header.width + ix) as usize * channels;
+                        for c in 0..channels {
+                            set_sample(
+                                row,
+                                x as usize * channels + c,
+                                header.bit_depth,
+                                samples[at + c],
+                            );
+                        }
+                    }
+                }
+                SubImage {
+                    width,
+                    height,
+                    data,
+                }
+            })
+            .collect();
+        ImageData {
+            header,
+            passes,
+            transparency,
+        }
+    }
+
+    /// every pixel in row order as RGBA scaled to 16 bits, with the palette and tRNS applied
+    pub fn to_rgba(&self, palette: Option<&[[u8; 3]]>) -> Result<Vec<[u16; 4]>> {
+        let header = &self.header;
+        let max = (1u32 << header.bit_depth) - 1;
+        let scale = |sample: u16| (sample as u32 * 65535 / max) as u16;
+        let channels = header.channels();
+        let mut pixels = vec![[0u16; 4]; header.width as usize * header.height as usize];
+        for (pass, sub) in self.passes.iter().enumerate() {
+            let row_len = header.row_len(sub.width);
+            for y in 0..sub.height {
+                let row = &sub.data[y as usize * row_len..(y as usize + 1) * row_len];
+                for x in 0..sub.width {
+                    let sample =
+                        |c: usize| get_sample(row, x as usize * channels + c, header.bit_depth);
+                    let rgba = match header.color_type {
+                        ColorType::Grayscale => {
+                            let gray = sample(0);
+                            let alpha = match self.transparency {
+                                Some(Transparency::GrayKey(key)) if key == gray => 0,
+                                _ => 65535,
+                            };
+                            [scale(gray), scale(gray), scale(gray), alpha]
+                        }
+                        ColorType::GrayscaleAlpha => {
+                            let gray = scale(sample(0));
+                            [gray, gray, gray, scale(sample(1))]
+                        }
+                        ColorType::Rgb => {
+                            let (r, g, b) = (sample(0), sample(1), sample(2));
+                            let alpha = match self.transparency {
+                                Some(Transparency::RgbKey(kr, kg, kb))
+                                    if (kr, kg, kb) == (r, g, b) =>
+                                {
+                                    0
+                                }
+                                _ => 65535,
+                            };
+                            [scale(r), scale(g), scale(b), alpha]
+                        }
+                        ColorType::Rgba => [
+                            scale(sample(0)),
+                            scale(sample(1)),
+                            scale(sample(2)),
+                            scale(sample(3)),
+                        ],
+                        ColorType::Indexed => {
+                            let index = sample(0) as usize;
+                            let [r, g, b] = *palette
+                                .and_then(|palette| palette.get(index))
+                                .ok_or_else(|| {
+                                    Error::from(format!(
+                                        "palette index {} has no PLTE entry",
+                                        index
+                                    ))
+                                })?;
+                            let alpha = match &self.transparency {
+                                Some(Transparency::PaletteAlpha(alpha)) => {
+                                    alpha.get(index).copied().unwrap_or(255)
+                                }
+                                _ => 255,
+                            };
+                            [r, g, b, alpha].map(|value| value as u16 * 257)
+                        }
+                    };
+                    let (ix, iy) = header.pass_to_image(pass, x, y);
+                    pixels[(iy * header.width + ix) as usize] = rgba;
+                }
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// a non interlaced RGBA image of `width` x `height` pixels in row order, at 8 bits per
+    /// sample unless `sixteen_bit`
+    pub fn from_rgba(width: u32, height: u32, pixels: &[[u16; 4]], sixteen_bit: bool) -> ImageData {
+        let header = Header {
+            width,
+            height,
+            bit_depth: if sixteen_bit { 16 } else { 8 },
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let data = pixels
+            .iter()
+            .flatten()
+            .flat_map(|sample| match sixteen_bit {
+                true => sample.to_be_bytes().to_vec(),
+                false => vec![(sample >> 8) as u8],
+            })
+            .collect();
+        ImageData {
+            header,
+            passes: vec![SubImage {
+                width,
+                height,
+                data,
+            }],
+            transparency: None,
+        }
+    }
+
+    /// encode the pixels into the IDAT chunks of the png, the chunks stay about as large as they were
+    pub fn write_to(&self, png: &mut Png) -> Result<()> {
+        let idat_len = png
+            .chunks_by_type("IDAT")
+            .iter()
+            .map(|chunk| chunk.length())
+            .max()
+            .unwrap_or_default()
+            .max(8192);
+        png.replace_idat(&self.encode()?, idat_len)
+    }
+
+    /// filter every scanline with the adaptive heuristic and deflate the result into an IDAT stream
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        self.encode_with(None, flate2::Compression::best())
+    }
+
+    /// like `encode`, `filter` forces one filter type for all scanlines instead of the heuristic
+    pub fn encode_with(&self, filter: Option<u8>, level: flate2::Compression) -> Result<Vec<u8>> {
+        let stride = self.header.filter_stride();
+        let mut raw = Vec::with_capacity(self.header.raw_len()?);
+        for pass in self.passes.iter().filter(|pass| !pass.data.is_empty()) {
+            let row_len = self.header.row_len(pass.width);
+            for y in 0..pass.height as usize {
+                let line = &pass.data[y * row_len..(y + 1) * row_len];
+                let previous = if y == 0 {
+                    None
+                } else {
+                    Some(&pass.data[(y - 1) * row_len..y * row_len])
+                };
+                let filtered = match filter {
+                    Some(filter) => apply_filter(filter, line, previous, stride),
+                    None => (0..5)
+                        .map(|filter| apply_filter(filter, line, previous, stride))
+                        .min_by_key(|filtered| {
+                            // minimum sum of absolute differences
+                            filtered[1..]
+                                .iter()
+                                .map(|&b| (b as i8).unsigned_abs() as u64)
+                                .sum::<u64>()
+                        })
+                        .unwrap(),
+                };
+                raw.extend_from_slice(&filtered);
+            }
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+        encoder.write_all(&raw)?;
+        Ok(encoder.finish()?)
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// reverse the filter of one scanline into `out`
+fn unfilter(
+    filter: u8,
+    line: &[u8],
+    previous: Option<&[u8]>,
+    out: &mut [u8],
+    stride: usize,
+) -> Result<()> {
+    for i in 0..line.len() {
+        let a = if i >= stride { out[i - stride] } else { 0 };
+        let b = previous.map_or(0, |previous| previous[i]);
+        let c = match previous {
+            Some(previous) if i >= stride => previous[i - stride],
+            _ => 0,
+        };
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return Err(Error::from(format!("invalid filter type {}", filter))),
+        };
+        out[i] = line[i].wrapping_add(predictor);
+    }
+    Ok(())
+}
+
+/// filter one scanline, the result starts with the filter type byte
+fn apply_filter(filter: u8, line: &[u8], previous: Option<&[u8]>, stride: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(line.len() + 1);
+    out.push(filter);
+    for i in 0..line.len() {
+        let a = if i >= stride { line[i - stride] } else { 0 };
+        let b = previous.map_or(0, |previous| previous[i]);
+        let c = match previous {
+            Some(previous) if i >= stride => previous[i - stride],
+            _ => 0,
+        };
+        let predictor = match filter {
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => 0,
+        };
+        out.push(line[i].wrapping_sub(predictor));
+    }
+    out
+}
+
+/// read sample `index` of a scanline with the given bit depth
+pub fn get_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        depth => {
+            let per_byte = 8 / depth as usize;
+            let shift = 8 - depth as usize * (index % per_byte + 1);
+            ((row[index / per_byte] >> shift) & ((1 << depth) - 1)) as u16
+        }
+    }
+}
+
+/// write sample `index` of a scanline with the given bit depth
+pub fn set_sample(row: &mut [u8], index: usize, bit_depth: u8, value: u16) {
+    match bit_depth {
+        16 => row[index * 2..index * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+        8 => row[index] = value as u8,
+        depth => {
+            let per_byte = 8 / depth as usize;
+            let shift = 8 - depth as usize * (index % per_byte + 1);
+            let mask = (((1u16 << depth) - 1) as u8) << shift;
+            let byte = &mut row[index / per_byte];
+            *byte = (*byte & !mask) | (((value as u8) << shift) & mask);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, chunk_type::ChunkType};
+    use std::str::FromStr;
+
+    fn png_from_parts(header: &Header, idat: Vec<u8>) -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), header.to_bytes()),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), idat),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    // 5x3 RGB 8-bit Adam7 image, pixel (x, y) is (x * 40, y * 80, x + y), every scanline uses filter 0
+    #[rustfmt::skip]
+    const INTERLACED_PNG: [u8; 105] = [
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 5, 0, 0, 0, 3, 8,
+        2, 0, 0, 1, 163, 83, 98, 57, 0, 0, 0, 48, 73, 68, 65, 84, 120, 218, 13, 198, 73, 1, 0, 32,
+        12, 4, 177, 233, 1, 90, 42, 103, 229, 84, 206, 74, 133, 188, 2, 159, 105, 68, 254, 164,
+        220, 246, 101, 136, 165, 24, 215, 250, 128, 98, 148, 82, 173, 218, 58, 15, 219, 165, 9,
+        142, 107, 246, 179, 239, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
+    ];
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = Header {
+            width: 50,
+            height: 7,
+            bit_depth: 4,
+            color_type: ColorType::Indexed,
+            interlaced: true,
+        };
+        assert_eq!(
+            Header::try_from(header.to_bytes().as_ref()).unwrap(),
+            header
+        );
+        assert_eq!(header.row_len(50), 25);
+        assert_eq!(header.filter_stride(), 1);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut bytes = Header {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        }
+        .to_bytes();
+        bytes[8] = 4;
+        assert!(Header::try_from(bytes.as_ref()).is_err());
+        bytes[8] = 8;
+        bytes[9] = 5;
+        assert!(Header::try_from(bytes.as_ref()).is_err());
+        assert!(Header::try_from(&bytes[..12]).is_err());
+    }
+
+    #[test]
+    fn test_adam7_pass_sizes() {
+        let header = Header {
+            width: 5,
+            height: 3,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: true,
+        };
+        assert_eq!(
+            header.pass_sizes(),
+            vec![(1, 1), (1, 1), (2, 0), (1, 1), (3, 1), (2, 2), (5, 1)]
+        );
+        assert_eq!(header.pass_to_image(5, 1, 1), (3, 2));
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        let png = Png::try_from(&INTERLACED_PNG[..]).unwrap();
+        let image = ImageData::decode(&png).unwrap();
+        for (pass, sub) in image.passes.iter().enumerate() {
+            let row_len = image.header.row_len(sub.width);
+            for y in 0..sub.height {
+                for x in 0..sub.width {
+                    let (ix, iy) = image.header.pass_to_image(pass, x, y);
+                    let offset = y as usize * row_len + x as usize * 3;
+                    assert_eq!(
+                        &sub.data[offset..offset + 3],
+                        &[ix as u8 * 40, iy as u8 * 80, (ix + iy) as u8]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        for (bit_depth, color_type, interlaced) in [
+            (8, ColorType::Rgba, false),
+            (16, ColorType::Rgb, true),
+            (2, ColorType::Grayscale, true),
+            (4, ColorType::Indexed, false),
+        ] {
+            let header = Header {
+                width: 13,
+                height: 9,
+                bit_depth,
+                color_type,
+                interlaced,
+            };
+            let passes = header
+                .pass_sizes()
+                .into_iter()
+                .map(|(width, height)| {
+                    let len = if width == 0 || height == 0 {
+                        0
+                    } else {
+                        header.row_len(width) * height as usize
+                    };
+                    SubImage {
+                        width,
+                        height,
+                        data: (0..len).map(|i| (i * 37 % 256) as u8).collect(),
+                    }
+                })
+                .collect();
+            let image = ImageData {
+                header,
+                passes,
+                transparency: None,
+            };
+            let png = png_from_parts(&header, image.encode().unwrap());
+            assert_eq!(ImageData::decode(&png).unwrap(), image);
+        }
+    }
+
+    #[test]
+    fn test_truncated_idat() {
+        let png = Png::try_from(&INTERLACED_PNG[..]).unwrap();
+        let header = Header::from_png(&png).unwrap();
+        let mut image = ImageData::decode(&png).unwrap();
+        image.passes[6].data.truncate(0);
+        let png = png_from_parts(&header, image.encode().unwrap());
+        assert!(ImageData::decode(&png).is_err());
+    }
+
+    #[test]
+    fn test_transparency() {
+        let key = Transparency::parse(&[0, 1, 0, 2, 0, 3], ColorType::Rgb).unwrap();
+        assert_eq!(key, Transparency::RgbKey(1, 2, 3));
+        assert_eq!(key.to_bytes(), vec![0, 1, 0, 2, 0, 3]);
+        assert_eq!(
+            Transparency::parse(&[0, 9], ColorType::Grayscale).unwrap(),
+            Transparency::GrayKey(9)
+        );
+        assert!(Transparency::parse(&[0, 9], ColorType::Rgb).is_err());
+        assert!(Transparency::parse(&[0, 9], ColorType::Rgba).is_err());
+        assert!(Transparency::parse(&[0; 257], ColorType::Indexed).is_err());
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut row = vec![0b1011_0010, 0b0111_0000];
+        assert_eq!(get_sample(&row, 0, 2), 0b10);
+        assert_eq!(get_sample(&row, 3, 2), 0b10);
+        assert_eq!(get_sample(&row, 1, 4), 0b0010);
+        set_sample(&mut row, 2, 4, 0b1111);
+        assert_eq!(row, vec![0b1011_0010, 0b1111_0000]);
+        set_sample(&mut row, 0, 16, 0x1234);
+        assert_eq!(get_sample(&row, 0, 16), 0x1234);
+        set_sample(&mut row, 7, 1, 1);
+        assert_eq!(row[0], 0x13);
+    }
+
+    #[test]
+    fn test_pixel_samples() {
+        let image = ImageData::decode(&Png::try_from(&INTERLACED_PNG[..]).unwrap()).unwrap();
+        let samples = image.samples();
+        let at = |x: usize, y: usize| &samples[(y * 5 + x) * 3..(y * 5 + x) * 3 + 3];
+        assert_eq!(at(3, 2), [120, 160, 5]);
+        assert_eq!(ImageData::from_samples(image.header, &samples, None), image);
+
+        let rgba = image.to_rgba(None).unwrap();
+        assert_eq!(rgba[2 * 5 + 3], [120 * 257, 160 * 257, 5 * 257, 65535]);
+        let image = ImageData::from_rgba(5, 3, &rgba, false);
+        assert_eq!(
+            image.samples()[(2 * 5 + 3) * 4..(2 * 5 + 4) * 4],
+            [120, 160, 5, 255]
+        );
+    }
+
+    #[test]
+    fn test_to_rgba() {
+        let header = Header {
+            width: 3,
+            height: 1,
+            bit_depth: 2,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        };
+        let image = ImageData::from_samples(
+            header,
+            &[0, 1, 1],
+            Some(Transparency::PaletteAlpha(vec![0])),
+        );
+        let palette = [[10, 20, 30], [40, 50, 60]];
+        let rgba = image.to_rgba(Some(&palette)).unwrap();
+        assert_eq!(rgba[0], [10 * 257, 20 * 257, 30 * 257, 0]);
+        assert_eq!(rgba[1], [40 * 257, 50 * 257, 60 * 257, 65535]);
+        // index 2 has no palette entry
+        assert!(ImageData::from_samples(header, &[2, 2, 2], None)
+            .to_rgba(Some(&palette))
+            .is_err());
+
+        let gray = Header {
+            color_type: ColorType::Grayscale,
+            ..header
+        };
+        let image = ImageData::from_samples(gray, &[3, 1, 0], Some(Transparency::GrayKey(1)));
+        let rgba = image.to_rgba(None).unwrap();
+        assert_eq!(rgba[0], [65535, 65535, 65535, 65535]);
+        assert_eq!(rgba[1][3], 0);
+        assert_eq!(rgba[2], [0, 0, 0, 65535]);
+    }
+}
@@ -0,0 +1,274 @@
+/// integrity of the compressed image stream without unfiltering it: the zlib header, the deflate
+/// blocks, the Adler-32 trailer and the inflated length against IHDR.
+/// [RFC 1950](https://www.rfc-editor.org/rfc/rfc1950)
+use flate2::{Decompress, FlushDecompress, Status};
+
+use super::Header;
+use crate::png::Png;
+
+/// the largest deflate window, 2^15 bytes
+const MAX_WINDOW_BITS: u8 = 15;
+
+/// Adler-32 of `data`, continuing from `adler`
+fn adler32(adler: u32, data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    // 5552 bytes is the most that can be summed before b overflows
+    for block in data.chunks(5552) {
+        for byte in block {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// where a byte of the concatenated stream is in the file
+fn locate(png: &Png, offset: usize) -> String {
+    let mut start = 0;
+    for (i, chunk) in png.chunks_by_type("IDAT").iter().enumerate() {
+        if offset < start + chunk.length() {
+            return format!("at byte {} (IDAT {} byte {})", offset, i, offset - start);
+        }
+        start += chunk.length();
+    }
+    format!("at byte {} (the end of the IDAT data)", offset)
+}
+
+/// everything wrong with the IDAT zlib stream, empty when it inflates to the size IHDR expects
+pub fn stream_errors(png: &Png) -> Vec<String> {
+    let header = match Header::from_png(png) {
+        Ok(header) => header,
+        Err(e) => return vec![e.to_string()],
+    };
+    let stream = png.idat_stream();
+    if stream.len() < 2 {
+        return vec![format!(
+            "zlib header truncated {}",
+            locate(png, stream.len())
+        )];
+    }
+
+    let mut errors = Vec::new();
+    let (cmf, flg) = (stream[0], stream[1]);
+    if cmf & 0x0f != 8 {
+        errors.push(format!(
+            "compression method {} {}, only 8 (deflate) is allowed",
+            cmf & 0x0f,
+            locate(png, 0)
+        ));
+    }
+    if (cmf >> 4) + 8 > MAX_WINDOW_BITS {
+        errors.push(format!(
+            "window size 2^{} {} is over 32768 bytes",
+            (cmf >> 4) + 8,
+            locate(png, 0)
+        ));
+    }
+    if !(u16::from_be_bytes([cmf, flg])).is_multiple_of(31) {
+        errors.push(format!(
+            "header check bits {} do not make CMF FLG a multiple of 31",
+            locate(png, 1)
+        ));
+    }
+    if flg & 0x20 != 0 {
+        errors.push(format!(
+            "preset dictionary {} is not allowed in png",
+            locate(png, 1)
+        ));
+    }
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    // inflate into a reused buffer, only the length and the checksum are needed
+    let expected = match header.raw_len() {
+        Ok(expected) => expected,
+        Err(e) => return vec![e.to_string()],
+    };
+    let mut inflater = Decompress::new(false);
+    let mut buffer = vec![0; 32 * 1024];
+    let (mut inflated, mut adler) = (0usize, 1u32);
+    let end = loop {
+        let consumed = 2 + inflater.total_in() as usize;
+        let before = inflater.total_out();
+        let status = inflater.decompress(&stream[consumed..], &mut buffer, FlushDecompress::None);
+        let produced = (inflater.total_out() - before) as usize;
+        adler = adler32(adler, &buffer[..produced]);
+        inflated += produced;
+        let position = 2 + inflater.total_in() as usize;
+        // stop early, a small stream can inflate to far more than the image holds
+        if inflated > expected {
+            errors.push(format!(
+                "inflates to more than the {} bytes IHDR expects, stopped {}",
+                expected,
+                locate(png, position)
+            ));
+            return errors;
+        }
+        match status {
+            Err(e) => {
+                errors.push(format!(
+                    "invalid deflate data {}: {}",
+                    locate(png, position),
+                    e
+                ));
+                return errors;
+            }
+            Ok(Status::StreamEnd) => break position,
+            Ok(_) if produced == 0 && position == consumed => {
+                errors.push(format!(
+                    "deflate data ends without its final block {}",
+                    locate(png, stream.len())
+                ));
+                return errors;
+            }
+            Ok(_) => {}
+        }
+    };
+
+    match stream.get(end..end + 4) {
+        None => errors.push(format!("Adler-32 truncated {}", locate(png, end))),
+        Some(trailer) => {
+            let stored = u32::from_be_bytes(trailer.try_into().unwrap());
+            if stored != adler {
+                errors.push(format!(
+                    "Adler-32 {} is {:08x}, the inflated data gives {:08x}",
+                    locate(png, end),
+                    stored,
+                    adler
+                ));
+            }
+            if stream.len() > end + 4 {
+                errors.push(format!(
+                    "{} bytes after the zlib stream {}",
+                    stream.len() - end - 4,
+                    locate(png, end + 4)
+                ));
+            }
+        }
+    }
+    if inflated != expected {
+        errors.push(format!(
+            "inflates to {} bytes, IHDR expects {} ({} scanlines with their filter bytes{})",
+            inflated,
+            expected,
+            header
+                .pass_sizes()
+                .iter()
+                .filter(|(width, _)| *width > 0)
+                .map(|(_, height)| *height as usize)
+                .sum::<usize>(),
+            if header.interlaced {
+                " over 7 passes"
+            } else {
+                ""
+            }
+        ));
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+
+    fn with_stream(stream: &[u8], split: usize) -> Png {
+        let mut png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        png.replace_idat(stream, split).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(1, b"Wikipedia"), 0x11e60398);
+        let data = vec![255; 100_000];
+        assert_eq!(
+            adler32(adler32(1, &data[..7000]), &data[7000..]),
+            adler32(1, &data)
+        );
+    }
+
+    #[test]
+    fn test_valid_stream() {
+        let png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        assert!(stream_errors(&png).is_empty());
+        let mut split = png;
+        split.rechunk_idat(7).unwrap();
+        assert!(stream_errors(&split).is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_stream() {
+        let png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let stream = png.idat_stream();
+        let len = stream.len();
+
+        let mut header = stream.clone();
+        header[0] = 0x88;
+        let errors = stream_errors(&with_stream(&header, 100));
+        assert!(
+            errors[0].starts_with("window size 2^16 at byte 0 (IDAT 0 byte 0)"),
+            "{:?}",
+            errors
+        );
+
+        let mut checksum = stream.clone();
+        checksum[len - 1] ^= 1;
+        let errors = stream_errors(&with_stream(&checksum, 100));
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].contains(&format!("at byte {}", len - 4)),
+            "{:?}",
+            errors
+        );
+        assert!(errors[0].contains(&format!("byte {})", (len - 4) % 100)));
+
+        let errors = stream_errors(&with_stream(&stream[..len / 2], 100));
+        assert!(errors[0].starts_with("deflate data ends"), "{:?}", errors);
+
+        let mut trailing = stream.clone();
+        trailing.extend([0, 0]);
+        assert_eq!(
+            stream_errors(&with_stream(&trailing, 100)),
+            [format!(
+                "2 bytes after the zlib stream at byte {} (IDAT {} byte {})",
+                len,
+                len / 100,
+                len % 100
+            )]
+        );
+
+        // a valid stream of the wrong size
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &[0; 10]).unwrap();
+        let errors = stream_errors(&with_stream(&encoder.finish().unwrap(), Chunk::MAX_LENGTH));
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("inflates to 10 bytes"),
+            "{:?}",
+            errors
+        );
+
+        // a deflate bomb is not inflated to its end
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        let zeros = vec![0; 1 << 20];
+        for _ in 0..8 {
+            std::io::Write::write_all(&mut encoder, &zeros).unwrap();
+        }
+        let errors = stream_errors(&with_stream(&encoder.finish().unwrap(), Chunk::MAX_LENGTH));
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with(&format!(
+                "inflates to more than the {} bytes IHDR expects, stopped at byte",
+                Header::from_png(&png).unwrap().raw_len().unwrap()
+            )),
+            "{:?}",
+            errors
+        );
+    }
+}
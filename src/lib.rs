@@ -1,8 +1,20 @@
+mod analysis;
+mod apng;
 pub mod args;
-mod chunk;
-mod chunk_type;
+pub mod chunk;
+pub mod chunk_type;
+mod color;
 pub mod commands;
-mod png;
+mod compression;
+mod crypto;
+mod exif;
+pub mod image;
+mod metadata;
+mod optimize;
+mod payload;
+pub mod png;
+mod signature;
+mod stego;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -5,7 +5,10 @@ use png_info::commands::*;
 use png_info::Result;
 
 fn main() {
-    run();
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
 
 fn run() -> Result<()> {
@@ -27,6 +30,58 @@ fn run() -> Result<()> {
             input,
             commands: Print(_),
         } => print(&input)?,
+        Opt {
+            input,
+            commands: Keygen(args),
+        } => keygen(&input, args)?,
+        Opt {
+            input,
+            commands: Sign(args),
+        } => sign(input, args)?,
+        Opt {
+            input,
+            commands: Verify(args),
+        } => verify(input, args)?,
+        Opt {
+            input,
+            commands: Capacity(args),
+        } => capacity(input, args)?,
+        Opt {
+            input,
+            commands: Analyze(_),
+        } => analyze(&input)?,
+        Opt {
+            input,
+            commands: Color(args),
+        } => color(input, args)?,
+        Opt {
+            input,
+            commands: Dpi(command),
+        } => dpi(input, command)?,
+        Opt {
+            input,
+            commands: Exif(command),
+        } => exif(input, command)?,
+        Opt {
+            input,
+            commands: Time(command),
+        } => time(input, command)?,
+        Opt {
+            input,
+            commands: Apng(command),
+        } => apng(input, command)?,
+        Opt {
+            input,
+            commands: Idat(args),
+        } => idat(input, args)?,
+        Opt {
+            input,
+            commands: Validate(_),
+        } => validate(&input)?,
+        Opt {
+            input,
+            commands: Optimize(args),
+        } => optimize(input, args)?,
     }
     Ok(())
 }
@@ -0,0 +1,896 @@
+/// ancillary chunks describing the image rather than its colors: pHYs, tIME, bKGD, hIST, sBIT
+/// and sPLT.
+use std::{fmt, str::FromStr, time::SystemTime};
+
+use crate::{
+    chunk::Chunk,
+    color,
+    image::{ColorType, Header},
+    png::Png,
+    Error, Result,
+};
+
+/// meters per inch
+const INCH: f64 = 0.0254;
+
+/// what the pHYs pixel counts are per
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// only the aspect ratio is known
+    Unknown,
+    Meter,
+}
+
+/// pHYs: the intended pixel size or aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Phys {
+    pub x: u32,
+    pub y: u32,
+    pub unit: Unit,
+}
+
+impl Phys {
+    pub fn parse(data: &[u8]) -> Result<Phys> {
+        if data.len() != 9 {
+            return Err(Error::from(format!(
+                "pHYs must be 9 bytes, found {}",
+                data.len()
+            )));
+        }
+        let unit = match data[8] {
+            0 => Unit::Unknown,
+            1 => Unit::Meter,
+            unit => return Err(Error::from(format!("unknown pHYs unit {}", unit))),
+        };
+        Ok(Phys {
+            x: u32::from_be_bytes(data[0..4].try_into().unwrap()),
+            y: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            unit,
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [
+            self.x.to_be_bytes().as_ref(),
+            self.y.to_be_bytes().as_ref(),
+            &[(self.unit == Unit::Meter) as u8],
+        ]
+        .concat()
+    }
+
+    pub fn from_png(png: &Png) -> Result<Option<Phys>> {
+        png.chunk_by_type("pHYs")
+            .map(|chunk| Phys::parse(chunk.data()))
+            .transpose()
+    }
+
+    /// pixels per meter for the dots per inch, rounded as pHYs only stores integers
+    pub fn from_dpi(x: f64, y: f64) -> Result<Phys> {
+        let per_meter = |dpi: f64| -> Result<u32> {
+            let value = (dpi / INCH).round();
+            if !(1.0..=u32::MAX as f64).contains(&value) {
+                return Err(Error::from(format!("{} dpi is out of range", dpi)));
+            }
+            Ok(value as u32)
+        };
+        Ok(Phys {
+            x: per_meter(x)?,
+            y: per_meter(y)?,
+            unit: Unit::Meter,
+        })
+    }
+
+    /// dots per inch horizontally and vertically, when the unit is known
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        (self.unit == Unit::Meter).then_some((self.x as f64 * INCH, self.y as f64 * INCH))
+    }
+
+    /// insert or replace pHYs, it must come before IDAT
+    pub fn write_to(self, png: &mut Png) {
+        png.set_chunk_before(
+            Chunk::new("pHYs".parse().unwrap(), self.to_bytes()),
+            &["IDAT"],
+        );
+    }
+}
+
+impl fmt::Display for Phys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.dpi() {
+            Some((x, y)) => write!(
+                f,
+                "{}x{} pixels per meter ({:.0}x{:.0} dpi)",
+                self.x, self.y, x, y
+            ),
+            None => write!(f, "aspect ratio {}:{}", self.x, self.y),
+        }
+    }
+}
+
+/// days in the month of the year, with the Gregorian leap years
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// days since 1970-01-01, negative before it
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// the year, month and day of a day since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// tIME: the last modification of the image, in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    /// 60 for a leap second
+    pub second: u8,
+}
+
+impl Time {
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Result<Time> {
+        if !(1..=12).contains(&month) {
+            return Err(Error::from(format!("tIME month {} is out of range", month)));
+        }
+        if day < 1 || day > days_in_month(year, month) {
+            return Err(Error::from(format!(
+                "tIME day {} is out of range for {}-{:02}",
+                day, year, month
+            )));
+        }
+        if hour > 23 || minute > 59 || second > 60 {
+            return Err(Error::from(format!(
+                "tIME time {:02}:{:02}:{:02} is out of range",
+                hour, minute, second
+            )));
+        }
+        Ok(Time {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Time> {
+        if data.len() != 7 {
+            return Err(Error::from(format!(
+                "tIME must be 7 bytes, found {}",
+                data.len()
+            )));
+        }
+        Time::new(
+            u16::from_be_bytes([data[0], data[1]]),
+            data[2],
+            data[3],
+            data[4],
+            data[5],
+            data[6],
+        )
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [
+            self.year.to_be_bytes().as_ref(),
+            &[self.month, self.day, self.hour, self.minute, self.second],
+        ]
+        .concat()
+    }
+
+    pub fn from_png(png: &Png) -> Result<Option<Time>> {
+        png.chunk_by_type("tIME")
+            .map(|chunk| Time::parse(chunk.data()))
+            .transpose()
+    }
+
+    /// `minutes` after midnight of 1970-01-01, the seconds kept apart for leap seconds
+    fn from_minutes(minutes: i64, second: u8) -> Result<Time> {
+        let (year, month, day) = civil_from_days(minutes.div_euclid(24 * 60));
+        let minute_of_day = minutes.rem_euclid(24 * 60);
+        let year = u16::try_from(year)
+            .map_err(|_| Error::from(format!("year {} does not fit in tIME", year)))?;
+        Time::new(
+            year,
+            month as u8,
+            day as u8,
+            (minute_of_day / 60) as u8,
+            (minute_of_day % 60) as u8,
+            second,
+        )
+    }
+
+    /// the current UTC time
+    pub fn now() -> Result<Time> {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| Error::from("the system clock is before 1970"))?
+            .as_secs() as i64;
+        Time::from_minutes(seconds.div_euclid(60), seconds.rem_euclid(60) as u8)
+    }
+
+    /// insert or replace tIME, it may be placed anywhere
+    pub fn write_to(self, png: &mut Png) {
+        png.set_chunk_before(Chunk::new("tIME".parse().unwrap(), self.to_bytes()), &[]);
+    }
+}
+
+/// RFC 3339, `2024-01-02T03:04:05Z`; other offsets are converted to UTC and
+/// fractions of a second are dropped as tIME can not store them
+impl FromStr for Time {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Time> {
+        let invalid = || Error::from(format!("{} is not an RFC 3339 date and time", s));
+        let number = |text: &str| -> Result<i64> {
+            if text.is_empty() || !text.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            text.parse().map_err(|_| invalid())
+        };
+        let (date, time) = s.split_once(['T', 't', ' ']).ok_or_else(invalid)?;
+        let date: Vec<&str> = date.split('-').collect();
+        let [year, month, day] = date[..] else {
+            return Err(invalid());
+        };
+
+        let (time, offset) = match time.strip_suffix(['Z', 'z']) {
+            Some(time) => (time, 0),
+            None => {
+                let at = time.rfind(['+', '-']).ok_or_else(invalid)?;
+                let (hours, minutes) = time[at + 1..].split_once(':').ok_or_else(invalid)?;
+                let offset = number(hours)? * 60 + number(minutes)?;
+                let sign = if time.as_bytes()[at] == b'-' { -1 } else { 1 };
+                (&time[..at], sign * offset)
+            }
+        };
+        let time = time.split('.').next().unwrap_or_default();
+        let time: Vec<&str> = time.split(':').collect();
+        let [hour, minute, second] = time[..] else {
+            return Err(invalid());
+        };
+
+        let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+        let (hour, minute, second) = (number(hour)?, number(minute)?, number(second)?);
+        // validate the local time before moving it to UTC
+        Time::new(
+            u16::try_from(year).map_err(|_| invalid())?,
+            u8::try_from(month).map_err(|_| invalid())?,
+            u8::try_from(day).map_err(|_| invalid())?,
+            u8::try_from(hour).map_err(|_| invalid())?,
+            u8::try_from(minute).map_err(|_| invalid())?,
+            u8::try_from(second).map_err(|_| invalid())?,
+        )?;
+        let minutes = days_from_civil(year, month, day) * 24 * 60 + hour * 60 + minute - offset;
+        Time::from_minutes(minutes, second as u8)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// bKGD: the color to show the image on, in the form the color type calls for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    PaletteIndex(u8),
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+/// a sample of a 16 bit field must fit the bit depth
+fn check_sample(what: &str, value: u16, bit_depth: u8) -> Result<()> {
+    if bit_depth < 16 && value >> bit_depth != 0 {
+        return Err(Error::from(format!(
+            "{} {} does not fit bit depth {}",
+            what, value, bit_depth
+        )));
+    }
+    Ok(())
+}
+
+impl Background {
+    /// `palette_len` is the number of PLTE entries, an index must point to one of them
+    pub fn parse(data: &[u8], header: &Header, palette_len: usize) -> Result<Background> {
+        let be_u16 = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        let background = match (header.color_type, data.len()) {
+            (ColorType::Indexed, 1) => {
+                if data[0] as usize >= palette_len {
+                    return Err(Error::from(format!(
+                        "bKGD index {} is past the {} palette entries",
+                        data[0], palette_len
+                    )));
+                }
+                Background::PaletteIndex(data[0])
+            }
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, 2) => Background::Gray(be_u16(0)),
+            (ColorType::Rgb | ColorType::Rgba, 6) => {
+                Background::Rgb(be_u16(0), be_u16(2), be_u16(4))
+            }
+            (color_type, len) => {
+                return Err(Error::from(format!(
+                    "bKGD of {} bytes does not match color type {}",
+                    len,
+                    color_type.code()
+                )))
+            }
+        };
+        match background {
+            Background::PaletteIndex(_) => {}
+            Background::Gray(gray) => check_sample("bKGD gray", gray, header.bit_depth)?,
+            Background::Rgb(r, g, b) => {
+                for sample in [r, g, b] {
+                    check_sample("bKGD sample", sample, header.bit_depth)?;
+                }
+            }
+        }
+        Ok(background)
+    }
+
+    pub fn from_png(png: &Png) -> Result<Option<Background>> {
+        let Some(chunk) = png.chunk_by_type("bKGD") else {
+            return Ok(None);
+        };
+        let header = Header::from_png(png)?;
+        let palette_len = png.palette()?.map_or(0, |palette| palette.len());
+        Background::parse(chunk.data(), &header, palette_len).map(Some)
+    }
+}
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Background::PaletteIndex(index) => write!(f, "palette entry {}", index),
+            Background::Gray(gray) => write!(f, "gray {}", gray),
+            Background::Rgb(r, g, b) => write!(f, "RGB ({}, {}, {})", r, g, b),
+        }
+    }
+}
+
+/// hIST: how often each palette entry is used, scaled to fit 16 bits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram(pub Vec<u16>);
+
+impl Histogram {
+    /// there must be one frequency for each of the `palette_len` PLTE entries
+    pub fn parse(data: &[u8], palette_len: usize) -> Result<Histogram> {
+        if data.len() != palette_len * 2 {
+            return Err(Error::from(format!(
+                "hIST of {} bytes does not match the {} palette entries",
+                data.len(),
+                palette_len
+            )));
+        }
+        Ok(Histogram(
+            data.chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect(),
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+
+    pub fn from_png(png: &Png) -> Result<Option<Histogram>> {
+        let Some(chunk) = png.chunk_by_type("hIST") else {
+            return Ok(None);
+        };
+        let palette = png
+            .palette()?
+            .ok_or_else(|| Error::from("hIST without a PLTE chunk"))?;
+        Histogram::parse(chunk.data(), palette.len()).map(Some)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unused = self.0.iter().filter(|value| **value == 0).count();
+        write!(f, "{} frequencies, {} unused", self.0.len(), unused)
+    }
+}
+
+/// sBIT: the bits of each channel that were significant in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignificantBits {
+    pub color_type: ColorType,
+    /// one per channel of the color type, red, green and blue for indexed images
+    pub bits: Vec<u8>,
+}
+
+impl SignificantBits {
+    fn channels(color_type: ColorType) -> &'static str {
+        match color_type {
+            ColorType::Grayscale => "Y",
+            ColorType::GrayscaleAlpha => "YA",
+            ColorType::Rgb | ColorType::Indexed => "RGB",
+            ColorType::Rgba => "RGBA",
+        }
+    }
+
+    pub fn parse(data: &[u8], header: &Header) -> Result<SignificantBits> {
+        let channels = SignificantBits::channels(header.color_type);
+        if data.len() != channels.len() {
+            return Err(Error::from(format!(
+                "sBIT of {} bytes does not match color type {}",
+                data.len(),
+                header.color_type.code()
+            )));
+        }
+        // the palette entries are 8 bit whatever the index depth is
+        let sample_depth = match header.color_type {
+            ColorType::Indexed => 8,
+            _ => header.bit_depth,
+        };
+        if let Some(bits) = data
+            .iter()
+            .find(|bits| **bits == 0 || **bits > sample_depth)
+        {
+            return Err(Error::from(format!(
+                "sBIT of {} bits is out of range for {} bit samples",
+                bits, sample_depth
+            )));
+        }
+        Ok(SignificantBits {
+            color_type: header.color_type,
+            bits: data.to_vec(),
+        })
+    }
+
+    pub fn from_png(png: &Png) -> Result<Option<SignificantBits>> {
+        let Some(chunk) = png.chunk_by_type("sBIT") else {
+            return Ok(None);
+        };
+        SignificantBits::parse(chunk.data(), &Header::from_png(png)?).map(Some)
+    }
+}
+
+impl fmt::Display for SignificantBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channels: Vec<String> = SignificantBits::channels(self.color_type)
+            .chars()
+            .zip(&self.bits)
+            .map(|(channel, bits)| format!("{}{}", channel, bits))
+            .collect();
+        write!(f, "{}", channels.join(" "))
+    }
+}
+
+/// an entry of sPLT, the samples are 8 or 16 bits depending on the palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuggestedEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    pub frequency: u16,
+}
+
+/// sPLT: a palette suggested for displays that can show few colors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    /// 8 or 16
+    pub sample_depth: u8,
+    pub entries: Vec<SuggestedEntry>,
+}
+
+impl SuggestedPalette {
+    pub fn parse(data: &[u8]) -> Result<SuggestedPalette> {
+        let end = data
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| Error::from("sPLT palette name is not terminated"))?;
+        let name: String = data[..end].iter().map(|byte| *byte as char).collect();
+        color::check_keyword("sPLT palette name", &name)?;
+        let sample_depth = *data
+            .get(end + 1)
+            .ok_or_else(|| Error::from("sPLT is missing the sample depth"))?;
+        let entry_len = match sample_depth {
+            8 => 6,
+            16 => 10,
+            depth => {
+                return Err(Error::from(format!(
+                    "sPLT sample depth must be 8 or 16, found {}",
+                    depth
+                )))
+            }
+        };
+        let entries = &data[end + 2..];
+        if !entries.len().is_multiple_of(entry_len) {
+            return Err(Error::from(format!(
+                "sPLT entries of {} bytes are not a whole number of {} byte entries",
+                entries.len(),
+                entry_len
+            )));
+        }
+        let entries = entries
+            .chunks(entry_len)
+            .map(|entry| {
+                let sample = |i: usize| match sample_depth {
+                    8 => entry[i] as u16,
+                    _ => u16::from_be_bytes([entry[i * 2], entry[i * 2 + 1]]),
+                };
+                SuggestedEntry {
+                    red: sample(0),
+                    green: sample(1),
+                    blue: sample(2),
+                    alpha: sample(3),
+                    frequency: u16::from_be_bytes([entry[entry_len - 2], entry[entry_len - 1]]),
+                }
+            })
+            .collect();
+        Ok(SuggestedPalette {
+            name,
+            sample_depth,
+            entries,
+        })
+    }
+
+    /// every sPLT, their names must be unique
+    pub fn from_png(png: &Png) -> Result<Vec<SuggestedPalette>> {
+        let palettes = png
+            .chunks_by_type("sPLT")
+            .iter()
+            .map(|chunk| SuggestedPalette::parse(chunk.data()))
+            .collect::<Result<Vec<_>>>()?;
+        for (i, palette) in palettes.iter().enumerate() {
+            if palettes[..i].iter().any(|other| other.name == palette.name) {
+                return Err(Error::from(format!(
+                    "sPLT name `{}` is used twice",
+                    palette.name
+                )));
+            }
+        }
+        Ok(palettes)
+    }
+}
+
+impl fmt::Display for SuggestedPalette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` of {} entries at {} bits",
+            self.name,
+            self.entries.len(),
+            self.sample_depth
+        )
+    }
+}
+
+/// chunk type, chunks it must follow, chunks it must precede, whether it may repeat
+const PLACEMENT_RULES: [(&str, &[&str], &[&str], bool); 6] = [
+    ("sBIT", &[], &["PLTE", "IDAT"], false),
+    ("bKGD", &["PLTE"], &["IDAT"], false),
+    ("hIST", &["PLTE"], &["IDAT"], false),
+    ("pHYs", &[], &["IDAT"], false),
+    ("sPLT", &[], &["IDAT"], true),
+    ("tIME", &[], &[], false),
+];
+
+/// metadata chunks that are repeated or on the wrong side of PLTE and IDAT
+pub fn placement_errors(png: &Png) -> Vec<String> {
+    let types: Vec<String> = png
+        .chunks()
+        .iter()
+        .map(|chunk| chunk.chunk_type().to_string())
+        .collect();
+    let first = |chunk_type: &str| types.iter().position(|t| t == chunk_type);
+    let last = |chunk_type: &str| types.iter().rposition(|t| t == chunk_type);
+    let mut errors = Vec::new();
+    for (chunk_type, after, before, repeatable) in PLACEMENT_RULES {
+        let count = types.iter().filter(|t| *t == chunk_type).count();
+        if count > 1 && !repeatable {
+            errors.push(format!(
+                "{} appears {} times, only one is allowed",
+                chunk_type, count
+            ));
+        }
+        for limit in after {
+            if let (Some(position), Some(limit_position)) = (first(chunk_type), last(limit)) {
+                if position < limit_position {
+                    errors.push(format!("{} must come after {}", chunk_type, limit));
+                }
+            }
+        }
+        for limit in before {
+            if let (Some(position), Some(limit_position)) = (last(chunk_type), first(limit)) {
+                if position > limit_position {
+                    errors.push(format!("{} must come before {}", chunk_type, limit));
+                }
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::image_png;
+
+    fn header(color_type: ColorType, bit_depth: u8) -> Header {
+        Header {
+            width: 1,
+            height: 1,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_phys() {
+        let phys = Phys::parse(&[0, 0, 0x0b, 0x13, 0, 0, 0x0b, 0x13, 1]).unwrap();
+        assert_eq!(phys.x, 2835);
+        assert_eq!(phys.to_string(), "2835x2835 pixels per meter (72x72 dpi)");
+        assert_eq!(Phys::parse(&phys.to_bytes()).unwrap(), phys);
+        assert!(Phys::parse(&[0; 8]).is_err());
+        assert!(Phys::parse(&[0, 0, 0, 1, 0, 0, 0, 1, 2]).is_err());
+
+        let aspect = Phys {
+            x: 1,
+            y: 2,
+            unit: Unit::Unknown,
+        };
+        assert_eq!(aspect.dpi(), None);
+        assert_eq!(aspect.to_string(), "aspect ratio 1:2");
+    }
+
+    #[test]
+    fn test_dpi() {
+        let phys = Phys::from_dpi(300.0, 150.0).unwrap();
+        assert_eq!((phys.x, phys.y), (11811, 5906));
+        let (x, y) = phys.dpi().unwrap();
+        assert_eq!((x.round(), y.round()), (300.0, 150.0));
+        assert!(Phys::from_dpi(0.0, 72.0).is_err());
+    }
+
+    #[test]
+    fn test_write_to() {
+        let mut png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        Phys::from_dpi(600.0, 600.0).unwrap().write_to(&mut png);
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types.iter().filter(|t| *t == "pHYs").count(), 1);
+
+        png.remove_chunk("pHYs").unwrap();
+        Phys::from_dpi(96.0, 96.0).unwrap().write_to(&mut png);
+        let position = |chunk_type: &str| {
+            png.chunks()
+                .iter()
+                .position(|chunk| chunk.chunk_type().to_string() == chunk_type)
+        };
+        assert_eq!(position("pHYs").unwrap() + 1, position("IDAT").unwrap());
+        assert_eq!(
+            Phys::from_png(&png)
+                .unwrap()
+                .unwrap()
+                .dpi()
+                .unwrap()
+                .0
+                .round(),
+            96.0
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        let time = Time::parse(&[0x07, 0xe8, 2, 29, 23, 59, 60]).unwrap();
+        assert_eq!(time.to_string(), "2024-02-29T23:59:60Z");
+        assert_eq!(Time::parse(&time.to_bytes()).unwrap(), time);
+        assert!(Time::parse(&[0x07, 0xe7, 2, 29, 0, 0, 0]).is_err());
+        assert!(Time::parse(&[0x07, 0xe8, 13, 1, 0, 0, 0]).is_err());
+        assert!(Time::parse(&[0x07, 0xe8, 1, 1, 24, 0, 0]).is_err());
+        assert!(Time::parse(&[0x07, 0xe8, 1, 1, 0, 0, 61]).is_err());
+        assert!(Time::parse(&[0x07, 0xe8, 1, 1, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        let time: Time = "2024-02-29T23:59:60Z".parse().unwrap();
+        assert_eq!(time, Time::new(2024, 2, 29, 23, 59, 60).unwrap());
+        assert_eq!(
+            "2024-03-01T01:30:05.250+02:00".parse::<Time>().unwrap(),
+            Time::new(2024, 2, 29, 23, 30, 5).unwrap()
+        );
+        assert_eq!(
+            "1999-12-31t22:00:00-03:00".parse::<Time>().unwrap(),
+            Time::new(2000, 1, 1, 1, 0, 0).unwrap()
+        );
+        for invalid in [
+            "2024-02-30T00:00:00Z",
+            "2024-01-01",
+            "2024-01-01T00:00Z",
+            "2024-01-01T00:00:00",
+            "2024-01-01T00:00:00+0200",
+            "2024-1a-01T00:00:00Z",
+        ] {
+            assert!(invalid.parse::<Time>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_now() {
+        let now = Time::now().unwrap();
+        assert!(now.year >= 2024);
+        assert_eq!(now.to_string().parse::<Time>().unwrap(), now);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+    }
+
+    #[test]
+    fn test_background() {
+        let indexed = header(ColorType::Indexed, 4);
+        assert_eq!(
+            Background::parse(&[2], &indexed, 3).unwrap(),
+            Background::PaletteIndex(2)
+        );
+        assert!(Background::parse(&[3], &indexed, 3).is_err());
+
+        let gray = header(ColorType::GrayscaleAlpha, 4);
+        assert_eq!(
+            Background::parse(&[0, 15], &gray, 0).unwrap(),
+            Background::Gray(15)
+        );
+        assert!(Background::parse(&[0, 16], &gray, 0).is_err());
+        assert!(Background::parse(&[0, 1, 0, 2, 0, 3], &gray, 0).is_err());
+
+        let header = header(ColorType::Rgba, 16);
+        let rgb = Background::parse(&[0, 1, 0, 2, 255, 255], &header, 0).unwrap();
+        assert_eq!(rgb, Background::Rgb(1, 2, 65535));
+        assert_eq!(rgb.to_string(), "RGB (1, 2, 65535)");
+    }
+
+    #[test]
+    fn test_histogram() {
+        let plte: &[u8] = &[0, 0, 0, 255, 255, 255];
+        let png = image_png(
+            ColorType::Indexed,
+            1,
+            &[("PLTE", plte), ("hIST", &[0, 7, 0, 0])],
+        );
+        let histogram = Histogram::from_png(&png).unwrap().unwrap();
+        assert_eq!(histogram, Histogram(vec![7, 0]));
+        assert_eq!(histogram.to_string(), "2 frequencies, 1 unused");
+        assert_eq!(histogram.to_bytes(), [0, 7, 0, 0]);
+
+        let png = image_png(ColorType::Indexed, 1, &[("PLTE", plte), ("hIST", &[0, 7])]);
+        assert!(Histogram::from_png(&png).is_err());
+        let png = image_png(ColorType::Rgb, 8, &[("hIST", &[0, 7])]);
+        assert!(Histogram::from_png(&png).is_err());
+    }
+
+    #[test]
+    fn test_significant_bits() {
+        let bits = SignificantBits::parse(&[5, 6, 5], &header(ColorType::Indexed, 2)).unwrap();
+        assert_eq!(bits.to_string(), "R5 G6 B5");
+        assert!(SignificantBits::parse(&[5, 6, 5], &header(ColorType::Rgba, 8)).is_err());
+        assert!(SignificantBits::parse(&[9], &header(ColorType::Grayscale, 8)).is_err());
+        assert!(SignificantBits::parse(&[0, 1], &header(ColorType::GrayscaleAlpha, 8)).is_err());
+        assert_eq!(
+            SignificantBits::parse(&[12, 1], &header(ColorType::GrayscaleAlpha, 16))
+                .unwrap()
+                .to_string(),
+            "Y12 A1"
+        );
+    }
+
+    fn splt_bytes(palette: &SuggestedPalette) -> Vec<u8> {
+        let mut data: Vec<u8> = palette.name.chars().map(|c| c as u8).collect();
+        data.extend([0, palette.sample_depth]);
+        for entry in &palette.entries {
+            for sample in [entry.red, entry.green, entry.blue, entry.alpha] {
+                match palette.sample_depth {
+                    8 => data.push(sample as u8),
+                    _ => data.extend(sample.to_be_bytes()),
+                }
+            }
+            data.extend(entry.frequency.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_suggested_palette() {
+        for sample_depth in [8, 16] {
+            let palette = SuggestedPalette {
+                name: String::from("web safe"),
+                sample_depth,
+                entries: vec![
+                    SuggestedEntry {
+                        red: 255,
+                        green: 0,
+                        blue: 51,
+                        alpha: 255,
+                        frequency: 1000,
+                    };
+                    3
+                ],
+            };
+            let data = splt_bytes(&palette);
+            assert_eq!(data.len(), 10 + 3 * (sample_depth as usize / 2 + 2));
+            assert_eq!(SuggestedPalette::parse(&data).unwrap(), palette);
+            assert!(SuggestedPalette::parse(&data[..data.len() - 1]).is_err());
+        }
+        assert!(SuggestedPalette::parse(b"name\x00\x04").is_err());
+        assert!(SuggestedPalette::parse(b"name\x08").is_err());
+        assert!(SuggestedPalette::parse(b" name\x00\x08").is_err());
+
+        let splt: &[u8] = b"a\x00\x08";
+        let png = image_png(ColorType::Rgb, 8, &[("sPLT", splt), ("sPLT", splt)]);
+        assert!(SuggestedPalette::from_png(&png).is_err());
+    }
+
+    #[test]
+    fn test_placement_errors() {
+        let plte: &[u8] = &[0, 0, 0];
+        let png = image_png(
+            ColorType::Indexed,
+            8,
+            &[
+                ("bKGD", &[0]),
+                ("PLTE", plte),
+                ("sBIT", &[8, 8, 8]),
+                ("IDAT", &[]),
+                ("tIME", &[0; 7]),
+                ("tIME", &[0; 7]),
+            ],
+        );
+        assert_eq!(
+            placement_errors(&png),
+            [
+                "sBIT must come before PLTE",
+                "bKGD must come after PLTE",
+                "tIME appears 2 times, only one is allowed"
+            ]
+        );
+    }
+}
@@ -0,0 +1,437 @@
+/// lossless recompression: the pixels are decoded to RGBA, every smaller color type and bit depth
+/// that holds them exactly is tried with each filter strategy and deflate level, and the smallest
+/// png wins. the result is decoded again and compared with the original pixels before it is used.
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    chunk::Chunk,
+    color,
+    image::{ColorType, Header, ImageData, Transparency},
+    png::Png,
+    Error, Result,
+};
+
+/// ancillary chunks kept when none are chosen, they change how the pixels look
+pub const DEFAULT_KEEP: [&str; 8] = [
+    "cICP", "iCCP", "sRGB", "cHRM", "gAMA", "mDCv", "cLLi", "pHYs",
+];
+
+/// chunks whose content depends on the color type, bit depth or palette. they are kept only
+/// when the pixels are stored the same way as before.
+const FORMAT_CHUNK_TYPES: [&str; 3] = ["bKGD", "hIST", "sBIT"];
+
+/// chunks written again from the chosen encoding
+const IMAGE_CHUNK_TYPES: [&str; 5] = ["IHDR", "PLTE", "tRNS", "IDAT", "IEND"];
+
+/// deflate levels tried, the default and the best
+const LEVELS: [u32; 2] = [6, 9];
+
+/// a way to store the pixels
+struct Candidate {
+    header: Header,
+    samples: Vec<u16>,
+    /// RGBA entries for indexed color, with the translucent ones first to keep tRNS short
+    palette: Vec<[u8; 4]>,
+}
+
+impl Candidate {
+    fn new(header: Header, pixels: &[[u16; 4]], sample: impl Fn(&[u16; 4]) -> Vec<u16>) -> Self {
+        Candidate {
+            header,
+            samples: pixels.iter().flat_map(sample).collect(),
+            palette: Vec::new(),
+        }
+    }
+
+    /// PLTE and tRNS for the palette, none for the other color types
+    fn palette_chunks(&self) -> Vec<Chunk> {
+        if self.palette.is_empty() {
+            return Vec::new();
+        }
+        let plte = self.palette.iter().flat_map(|entry| &entry[..3]).copied();
+        let mut chunks = vec![Chunk::new("PLTE".parse().unwrap(), plte.collect())];
+        let alpha: Vec<u8> = self
+            .palette
+            .iter()
+            .map(|entry| entry[3])
+            .take_while(|alpha| *alpha < 255)
+            .collect();
+        if !alpha.is_empty() {
+            chunks.push(Chunk::new("tRNS".parse().unwrap(), alpha));
+        }
+        chunks
+    }
+
+    fn transparency(&self) -> Option<Transparency> {
+        let alpha: Vec<u8> = self
+            .palette
+            .iter()
+            .map(|entry| entry[3])
+            .take_while(|alpha| *alpha < 255)
+            .collect();
+        (!alpha.is_empty()).then_some(Transparency::PaletteAlpha(alpha))
+    }
+}
+
+/// every encoding that stores the pixels exactly, the original RGBA one included
+fn candidates(width: u32, height: u32, pixels: &[[u16; 4]]) -> Vec<Candidate> {
+    let eight_bit = pixels.iter().flatten().all(|sample| sample % 257 == 0);
+    let opaque = pixels.iter().all(|pixel| pixel[3] == 65535);
+    let gray = pixels
+        .iter()
+        .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    let bit_depth = if eight_bit { 8 } else { 16 };
+    let header = |color_type: ColorType, bit_depth: u8| Header {
+        width,
+        height,
+        bit_depth,
+        color_type,
+        interlaced: false,
+    };
+    let scale = move |sample: u16| if eight_bit { sample >> 8 } else { sample };
+
+    let mut candidates = vec![Candidate::new(
+        header(ColorType::Rgba, bit_depth),
+        pixels,
+        |pixel| pixel.map(scale).to_vec(),
+    )];
+    if opaque {
+        candidates.push(Candidate::new(
+            header(ColorType::Rgb, bit_depth),
+            pixels,
+            |pixel| pixel[..3].iter().map(|sample| scale(*sample)).collect(),
+        ));
+    }
+    if gray {
+        candidates.push(Candidate::new(
+            header(ColorType::GrayscaleAlpha, bit_depth),
+            pixels,
+            |pixel| vec![scale(pixel[0]), scale(pixel[3])],
+        ));
+    }
+    if gray && opaque {
+        // the smallest depth whose steps hit every gray value
+        let gray_depth = [1u8, 2, 4]
+            .into_iter()
+            .find(|depth| {
+                let step = 255 / ((1u16 << depth) - 1) * 257;
+                eight_bit && pixels.iter().all(|pixel| pixel[0] % step == 0)
+            })
+            .unwrap_or(bit_depth);
+        let step = match gray_depth {
+            16 => 1,
+            depth => 65535 / ((1u16 << depth) - 1),
+        };
+        candidates.push(Candidate::new(
+            header(ColorType::Grayscale, gray_depth),
+            pixels,
+            |pixel| vec![pixel[0] / step],
+        ));
+    }
+    if eight_bit {
+        let mut colors: Vec<[u8; 4]> = pixels
+            .iter()
+            .map(|pixel| pixel.map(|sample| (sample >> 8) as u8))
+            .collect();
+        colors.sort_unstable_by_key(|color| (color[3] == 255, *color));
+        colors.dedup();
+        if colors.len() <= 256 {
+            let palette_depth = match colors.len() {
+                0..=2 => 1,
+                3..=4 => 2,
+                5..=16 => 4,
+                _ => 8,
+            };
+            let index: HashMap<[u8; 4], u16> = colors
+                .iter()
+                .enumerate()
+                .map(|(i, color)| (*color, i as u16))
+                .collect();
+            let mut candidate =
+                Candidate::new(header(ColorType::Indexed, palette_depth), pixels, |pixel| {
+                    vec![index[&pixel.map(|sample| (sample >> 8) as u8)]]
+                });
+            candidate.palette = colors;
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+/// the smallest png found
+pub struct Optimized {
+    pub png: Png,
+    pub header: Header,
+    /// `None` for the adaptive heuristic
+    pub filter: Option<u8>,
+    pub level: u32,
+}
+
+impl fmt::Display for Optimized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filter = match self.filter {
+            None => "adaptive",
+            Some(0) => "none",
+            Some(1) => "sub",
+            Some(2) => "up",
+            Some(3) => "average",
+            Some(_) => "paeth",
+        };
+        write!(
+            f,
+            "{:?} at {} bits, {} filter, deflate level {}",
+            self.header.color_type, self.header.bit_depth, filter, self.level
+        )
+    }
+}
+
+/// recompress the png, keeping the ancillary chunks of the `keep` types ("all" for every one)
+/// and a single IDAT. APNGs are refused as only the default image would be recompressed.
+pub fn optimize(png: &Png, keep: &[&str]) -> Result<Optimized> {
+    if png.chunk_by_type("acTL").is_some() {
+        return Err(Error::from(
+            "optimize does not handle APNG, the frames would be lost",
+        ));
+    }
+    let original = ImageData::decode(png)?;
+    let palette = png.palette()?;
+    let pixels = original.to_rgba(palette.as_deref())?;
+    let (width, height) = (original.header.width, original.header.height);
+
+    // an ICC profile is either GRAY for the grayscale color types or RGB for the others, so a kept
+    // iCCP pins the image to its side
+    let keeps_icc =
+        png.chunk_by_type("iCCP").is_some() && (keep.contains(&"all") || keep.contains(&"iCCP"));
+    let gray = original.header.color_type.is_grayscale();
+
+    let mut best: Option<(usize, Optimized)> = None;
+    for candidate in candidates(width, height, &pixels)
+        .into_iter()
+        .filter(|candidate| !keeps_icc || candidate.header.color_type.is_grayscale() == gray)
+    {
+        let image = ImageData::from_samples(
+            candidate.header,
+            &candidate.samples,
+            candidate.transparency(),
+        );
+        // the format chunks stay valid only when the pixels are stored the same way
+        let same_format = candidate.header == original.header
+            && candidate.transparency() == original.transparency
+            && candidate
+                .palette
+                .iter()
+                .map(|entry| [entry[0], entry[1], entry[2]])
+                .collect::<Vec<_>>()
+                == palette.clone().unwrap_or_default();
+        for filter in [None, Some(0), Some(1), Some(2), Some(3), Some(4)] {
+            for level in LEVELS {
+                let stream = image.encode_with(filter, flate2::Compression::new(level))?;
+                let rebuilt = rebuild(png, &candidate, stream, keep, same_format)?;
+                let size = rebuilt.as_bytes().len();
+                if best.as_ref().is_none_or(|(best_size, _)| size < *best_size) {
+                    let optimized = Optimized {
+                        png: rebuilt,
+                        header: candidate.header,
+                        filter,
+                        level,
+                    };
+                    best = Some((size, optimized));
+                }
+            }
+        }
+    }
+    let (_, optimized) = best.ok_or_else(|| Error::from("no encoding found"))?;
+
+    let decoded = ImageData::decode(&optimized.png)?;
+    if decoded.to_rgba(optimized.png.palette()?.as_deref())? != pixels {
+        return Err(Error::from(
+            "the optimized pixels differ from the original ones",
+        ));
+    }
+    Ok(optimized)
+}
+
+/// the chunks of the png with the new image data in place of IHDR, PLTE, tRNS and IDAT
+fn rebuild(
+    png: &Png,
+    candidate: &Candidate,
+    stream: Vec<u8>,
+    keep: &[&str],
+    same_format: bool,
+) -> Result<Png> {
+    if stream.len() > Chunk::MAX_LENGTH {
+        return Err(Error::from("the image data does not fit in one IDAT"));
+    }
+    let kept = |chunk: &Chunk| {
+        let chunk_type = chunk.chunk_type().to_string();
+        if IMAGE_CHUNK_TYPES.contains(&chunk_type.as_str()) {
+            return false;
+        }
+        if FORMAT_CHUNK_TYPES.contains(&chunk_type.as_str()) && !same_format {
+            return false;
+        }
+        chunk.chunk_type().is_critical()
+            || keep.contains(&"all")
+            || keep.contains(&chunk_type.as_str())
+    };
+    // PLTE and tRNS go where the first chunk that must follow PLTE was
+    let palette_at = png
+        .chunks()
+        .iter()
+        .position(|chunk| {
+            ["PLTE", "tRNS", "bKGD", "hIST", "IDAT"]
+                .contains(&chunk.chunk_type().to_string().as_str())
+        })
+        .unwrap_or(png.chunks().len());
+
+    let mut chunks = vec![Chunk::new(
+        "IHDR".parse().unwrap(),
+        candidate.header.to_bytes(),
+    )];
+    let mut idat = Some(Chunk::new("IDAT".parse().unwrap(), stream));
+    for (i, chunk) in png.chunks().iter().enumerate() {
+        if i == palette_at {
+            chunks.extend(candidate.palette_chunks());
+        }
+        if chunk.chunk_type().to_string() == "IDAT" {
+            chunks.extend(idat.take());
+        } else if kept(chunk) {
+            chunks.push(Chunk::new(
+                chunk.chunk_type().clone(),
+                chunk.data().to_vec(),
+            ));
+        }
+    }
+    chunks.push(Chunk::new("IEND".parse().unwrap(), Vec::new()));
+    let rebuilt = Png::from_chunks(chunks);
+    if let Some(error) = color::placement_errors(&rebuilt).first() {
+        return Err(Error::from(format!(
+            "optimized png is misordered: {}",
+            error
+        )));
+    }
+    Ok(rebuilt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng;
+
+    fn png_of(header: Header, samples: &[u16], extra: Vec<Chunk>) -> Png {
+        let mut png = apng::still_png(&ImageData::from_samples(header, samples, None)).unwrap();
+        for chunk in extra {
+            png.set_chunk_before(chunk, &["IDAT"]);
+        }
+        png
+    }
+
+    fn header(color_type: ColorType, bit_depth: u8) -> Header {
+        Header {
+            width: 16,
+            height: 16,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_reduce_to_gray() {
+        // opaque RGBA 16 bit where every pixel is black or white
+        let samples: Vec<u16> = (0..256)
+            .flat_map(|i| {
+                let value = if i % 3 == 0 { 65535 } else { 0 };
+                [value, value, value, 65535]
+            })
+            .collect();
+        let png = png_of(header(ColorType::Rgba, 16), &samples, Vec::new());
+        let optimized = optimize(&png, &DEFAULT_KEEP).unwrap();
+        assert_eq!(optimized.header.bit_depth, 1);
+        assert!(matches!(
+            optimized.header.color_type,
+            ColorType::Grayscale | ColorType::Indexed
+        ));
+        assert!(optimized.png.as_bytes().len() < png.as_bytes().len());
+    }
+
+    #[test]
+    fn test_reduce_to_palette() {
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 0]];
+        // in random order, a repeating pattern compresses well enough to keep RGBA
+        let mut state = 1u32;
+        let samples: Vec<u16> = (0..256)
+            .flat_map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                colors[(state >> 16) as usize % 3]
+            })
+            .collect();
+        let png = png_of(header(ColorType::Rgba, 8), &samples, Vec::new());
+        let optimized = optimize(&png, &DEFAULT_KEEP).unwrap();
+        assert_eq!(optimized.header.color_type, ColorType::Indexed);
+        assert_eq!(optimized.header.bit_depth, 2);
+        // translucent entries first, so tRNS only holds them
+        assert_eq!(
+            optimized.png.chunk_by_type("tRNS").unwrap().data(),
+            [0, 128]
+        );
+        let types: Vec<String> = optimized
+            .png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "PLTE", "tRNS", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_icc_keeps_color_side() {
+        // opaque gray pixels stored as RGB with an iCCP, the profile is RGB
+        let samples: Vec<u16> = (0..256u16).flat_map(|i| [i * 257; 3]).collect();
+        let icc = Chunk::new("iCCP".parse().unwrap(), b"icc\0\0profile".to_vec());
+        let png = png_of(header(ColorType::Rgb, 16), &samples, vec![icc]);
+        let optimized = optimize(&png, &DEFAULT_KEEP).unwrap();
+        assert!(!optimized.header.color_type.is_grayscale());
+        assert!(optimized.png.chunk_by_type("iCCP").is_some());
+
+        // without the profile the grayscale types are fine
+        let optimized = optimize(&png, &["gAMA"]).unwrap();
+        assert_eq!(optimized.header.color_type, ColorType::Grayscale);
+        assert!(optimized.png.chunk_by_type("iCCP").is_none());
+    }
+
+    #[test]
+    fn test_keep_chunks() {
+        // 16 bit samples that no smaller color type holds
+        let samples: Vec<u16> = (0..256 * 3).map(|i| (i * 7919 % 65536) as u16).collect();
+        let extra = vec![
+            Chunk::new("gAMA".parse().unwrap(), vec![0, 0, 177, 143]),
+            Chunk::new("sBIT".parse().unwrap(), vec![8, 8, 8]),
+            Chunk::new("tEXt".parse().unwrap(), b"Comment\0hi".to_vec()),
+        ];
+        let png = png_of(header(ColorType::Rgb, 16), &samples, extra);
+        let types = |png: &Png| -> Vec<String> {
+            png.chunks()
+                .iter()
+                .map(|chunk| chunk.chunk_type().to_string())
+                .collect()
+        };
+        let optimized = optimize(&png, &DEFAULT_KEEP).unwrap();
+        assert_eq!(optimized.header, header(ColorType::Rgb, 16));
+        assert_eq!(types(&optimized.png), ["IHDR", "gAMA", "IDAT", "IEND"]);
+        let optimized = optimize(&png, &["all"]).unwrap();
+        assert_eq!(
+            types(&optimized.png),
+            ["IHDR", "gAMA", "sBIT", "tEXt", "IDAT", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_refuse_apng() {
+        let frame = || Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let animation = apng::build(&[frame(), frame()], 100, 0, false).unwrap();
+        assert!(optimize(&animation, &DEFAULT_KEEP).is_err());
+        let optimized = optimize(&frame(), &DEFAULT_KEEP).unwrap();
+        assert!(optimized.png.as_bytes().len() < crate::png::tests::PNG_FILE.len());
+    }
+}
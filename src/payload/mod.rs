@@ -0,0 +1,362 @@
+/// binary file payloads and payloads split across several chunks.
+/// the file bytes are stored behind a small header with the original name, MIME type,
+/// length and a CRC-32 of the content, so `decode` can restore the file as it was.
+use crc::{Crc, CRC_32_ISO_HDLC};
+use rand_core::{OsRng, RngCore};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{chunk::Chunk, Error, Result};
+
+/// magic bytes at the start of a file payload
+pub const FILE_MAGIC: [u8; 4] = *b"PIFf";
+
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FilePayload {
+    pub name: String,
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+impl FilePayload {
+    /// read the file, the MIME type is guessed from its extension
+    pub fn read<T: AsRef<Path>>(path: T) -> Result<FilePayload> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(FilePayload {
+            mime: guess_mime(path).to_string(),
+            name,
+            data: std::fs::read(path)?,
+        })
+    }
+
+    /// layout: magic | name length (u16) | name | mime length (u16) | mime | data length (u64) | crc | data
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            FILE_MAGIC.as_ref(),
+            (self.name.len() as u16).to_be_bytes().as_ref(),
+            self.name.as_bytes(),
+            (self.mime.len() as u16).to_be_bytes().as_ref(),
+            self.mime.as_bytes(),
+            (self.data.len() as u64).to_be_bytes().as_ref(),
+            CRC32.checksum(&self.data).to_be_bytes().as_ref(),
+            self.data.as_slice(),
+        ]
+        .concat()
+    }
+
+    pub fn is_file_payload(data: &[u8]) -> bool {
+        data.len() >= 4 && data[0..4] == FILE_MAGIC
+    }
+}
+
+impl TryFrom<&[u8]> for FilePayload {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if !FilePayload::is_file_payload(value) {
+            return Err(Error::from("data is not a file payload"));
+        }
+        let mut reader = Reader {
+            data: value,
+            pivot: 4,
+        };
+        let name_len = u16::from_be_bytes(reader.take()?) as usize;
+        let name = String::from_utf8(reader.take_slice(name_len)?.to_vec())?;
+        let mime_len = u16::from_be_bytes(reader.take()?) as usize;
+        let mime = String::from_utf8(reader.take_slice(mime_len)?.to_vec())?;
+        let length = u64::from_be_bytes(reader.take()?) as usize;
+        let crc = u32::from_be_bytes(reader.take()?);
+        let data = reader.take_slice(length)?.to_vec();
+        if reader.pivot != value.len() {
+            return Err(Error::from("file payload has trailing bytes"));
+        }
+        if CRC32.checksum(&data) != crc {
+            return Err(Error::from("file payload checksum mismatch"));
+        }
+        Ok(FilePayload { name, mime, data })
+    }
+}
+
+/// magic bytes at the start of every part of a split payload
+pub const SEQUENCE_MAGIC: [u8; 4] = *b"PIFs";
+
+/// magic | payload id | index | total | crc of the whole payload
+pub const SEQUENCE_HEADER_LEN: usize = 4 + 4 * 4;
+
+/// one part of a payload split by `split`
+#[derive(Debug)]
+pub struct SequencePart<'a> {
+    /// shared by all parts of one payload
+    pub id: u32,
+    pub index: u32,
+    pub total: u32,
+    pub crc: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> SequencePart<'a> {
+    pub fn is_sequence_part(data: &[u8]) -> bool {
+        data.len() >= SEQUENCE_HEADER_LEN && data[0..4] == SEQUENCE_MAGIC
+    }
+
+    pub fn parse(data: &'a [u8]) -> Result<SequencePart<'a>> {
+        if !SequencePart::is_sequence_part(data) {
+            return Err(Error::from("data is not part of a split payload"));
+        }
+        let be_u32 =
+            |offset: usize| u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap());
+        Ok(SequencePart {
+            id: be_u32(4),
+            index: be_u32(8),
+            total: be_u32(12),
+            crc: be_u32(16),
+            data: &data[SEQUENCE_HEADER_LEN..],
+        })
+    }
+}
+
+/// split the payload into chunk datas of at most `max_chunk_len` bytes.
+/// payloads that already fit are returned unchanged, without a sequence header.
+pub fn split(data: &[u8], max_chunk_len: usize) -> Result<Vec<Vec<u8>>> {
+    let max_chunk_len = max_chunk_len.min(Chunk::MAX_LENGTH);
+    if data.len() <= max_chunk_len {
+        return Ok(vec![data.to_vec()]);
+    }
+    if max_chunk_len <= SEQUENCE_HEADER_LEN {
+        return Err(Error::from(format!(
+            "the chunk size must be larger than the {} bytes sequence header",
+            SEQUENCE_HEADER_LEN
+        )));
+    }
+    let parts: Vec<&[u8]> = data.chunks(max_chunk_len - SEQUENCE_HEADER_LEN).collect();
+    let total = u32::try_from(parts.len()).map_err(|_| Error::from("too many parts"))?;
+    let id = OsRng.next_u32();
+    let crc = CRC32.checksum(data);
+    Ok(parts
+        .iter()
+        .enumerate()
+        .map(|(index, part)| {
+            [
+                SEQUENCE_MAGIC.as_ref(),
+                id.to_be_bytes().as_ref(),
+                (index as u32).to_be_bytes().as_ref(),
+                total.to_be_bytes().as_ref(),
+                crc.to_be_bytes().as_ref(),
+                part,
+            ]
+            .concat()
+        })
+        .collect())
+}
+
+/// missing part indices listed in the error, the rest are counted
+const MAX_LISTED_MISSING: usize = 10;
+
+/// reassemble the payload the first part belongs to from the chunk datas, in any order.
+/// parts of other payloads are ignored.
+pub fn join<'a, I: IntoIterator<Item = &'a [u8]>>(datas: I) -> Result<Vec<u8>> {
+    let parts = datas
+        .into_iter()
+        .filter(|data| SequencePart::is_sequence_part(data))
+        .map(SequencePart::parse)
+        .collect::<Result<Vec<_>>>()?;
+    let first = parts
+        .first()
+        .ok_or_else(|| Error::from("no part of a split payload found"))?;
+    let (id, total, crc) = (first.id, first.total, first.crc);
+
+    // `total` comes from the file, so only the parts actually found are stored
+    let mut ordered: BTreeMap<u32, &[u8]> = BTreeMap::new();
+    for part in parts.iter().filter(|part| part.id == id) {
+        if part.total != total || part.crc != crc || part.index >= total {
+            return Err(Error::from(format!(
+                "part {} of the split payload has an inconsistent header",
+                part.index
+            )));
+        }
+        if ordered.insert(part.index, part.data).is_some() {
+            return Err(Error::from(format!(
+                "part {} of the split payload is duplicated",
+                part.index
+            )));
+        }
+    }
+    let missing_count = total as usize - ordered.len();
+    if missing_count > 0 {
+        let mut missing: Vec<String> = (0..total)
+            .filter(|index| !ordered.contains_key(index))
+            .take(MAX_LISTED_MISSING)
+            .map(|index| index.to_string())
+            .collect();
+        if missing_count > MAX_LISTED_MISSING {
+            missing.push(format!("and {} more", missing_count - MAX_LISTED_MISSING));
+        }
+        return Err(Error::from(format!(
+            "split payload is missing part(s) {} of {}",
+            missing.join(", "),
+            total
+        )));
+    }
+
+    let data: Vec<u8> = ordered.into_values().flatten().copied().collect();
+    if CRC32.checksum(&data) != crc {
+        return Err(Error::from("split payload checksum mismatch"));
+    }
+    Ok(data)
+}
+
+/// bounds checked reading of the header fields
+struct Reader<'a> {
+    data: &'a [u8],
+    pivot: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take_slice(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pivot
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| Error::from("file payload is truncated"))?;
+        let slice = &self.data[self.pivot..end];
+        self.pivot = end;
+        Ok(slice)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take_slice(N)?.try_into().unwrap())
+    }
+}
+
+fn guess_mime(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "md" => "text/plain",
+        "json" => "application/json",
+        "toml" => "application/toml",
+        "yaml" | "yml" => "application/yaml",
+        "xml" => "application/xml",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_payload() -> FilePayload {
+        FilePayload {
+            name: "config.bin".to_string(),
+            mime: "application/octet-stream".to_string(),
+            data: vec![0, 159, 146, 150, 255, 1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let payload = testing_payload();
+        let bytes = payload.to_bytes();
+        assert!(FilePayload::is_file_payload(&bytes));
+        assert_eq!(FilePayload::try_from(bytes.as_ref()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_corrupted_payload() {
+        let mut bytes = testing_payload().to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(FilePayload::try_from(bytes.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_truncated_payload() {
+        let bytes = testing_payload().to_bytes();
+        for len in [3, 5, 10, bytes.len() - 1] {
+            assert!(FilePayload::try_from(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let parts = split(&data, 100).unwrap();
+        assert_eq!(parts.len(), 13);
+        assert!(parts.iter().all(|part| part.len() <= 100));
+
+        let mut reordered: Vec<&[u8]> = parts.iter().map(|part| part.as_slice()).collect();
+        reordered.reverse();
+        reordered.swap(3, 7);
+        assert_eq!(join(reordered).unwrap(), data);
+    }
+
+    #[test]
+    fn test_small_payload_is_not_split() {
+        let parts = split(b"short", 100).unwrap();
+        assert_eq!(parts, vec![b"short".to_vec()]);
+        assert!(split(&[0; 100], SEQUENCE_HEADER_LEN).is_err());
+    }
+
+    #[test]
+    fn test_join_errors() {
+        let data = vec![7u8; 500];
+        let parts = split(&data, 120).unwrap();
+
+        let missing: Vec<&[u8]> = parts.iter().skip(1).map(|part| part.as_slice()).collect();
+        assert!(join(missing).is_err());
+
+        let mut corrupted = parts.clone();
+        let last = corrupted[2].len() - 1;
+        corrupted[2][last] ^= 1;
+        assert!(join(corrupted.iter().map(|part| part.as_slice())).is_err());
+
+        let duplicated = [parts[0].as_slice(), parts[0].as_slice()];
+        assert!(join(duplicated).is_err());
+
+        // a forged total must not be allocated or listed
+        let mut forged = parts[0].clone();
+        let total = SEQUENCE_MAGIC.len() + 8;
+        forged[total..total + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let error = join([forged.as_slice()]).unwrap_err().to_string();
+        assert!(
+            error.ends_with(&format!("10, and {} more of {}", u32::MAX - 11, u32::MAX)),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_join_ignores_other_payloads() {
+        let first = split(&[1u8; 300], 100).unwrap();
+        let second = split(&[2u8; 300], 100).unwrap();
+        let mixed = first
+            .iter()
+            .zip(second.iter())
+            .flat_map(|(a, b)| [a.as_slice(), b.as_slice()]);
+        assert_eq!(join(mixed).unwrap(), vec![1u8; 300]);
+    }
+
+    #[test]
+    fn test_guess_mime() {
+        assert_eq!(
+            guess_mime(Path::new("a/b/settings.JSON")),
+            "application/json"
+        );
+        assert_eq!(guess_mime(Path::new("bundle")), "application/octet-stream");
+    }
+}
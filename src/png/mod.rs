@@ -1,8 +1,10 @@
 use crate::chunk::Chunk;
-use crate::chunk_type::{self, ChunkType};
+use crate::chunk_type::ChunkType;
+use crate::image::{ColorType, Header, Transparency};
+use crate::stego::{self, StorageMethod};
 use crate::Error;
 
-use std::fmt::{self, Display};
+use std::fmt;
 #[derive(Debug)]
 pub struct Png {
     chunks: Vec<Chunk>,
@@ -23,20 +25,185 @@ impl Png {
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         self.chunks
             .iter()
-            .find(|chunk| &chunk.chunk_type().to_string() == chunk_type)
+            .find(|chunk| chunk.chunk_type().to_string() == chunk_type)
+    }
+
+    /// every chunk of the type, in file order
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&Chunk> {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type().to_string() == chunk_type)
+            .collect()
     }
 
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
 
+    /// insert the chunk before IEND, or append it when there is no IEND
+    pub fn insert_before_iend(&mut self, chunk: Chunk) {
+        match self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type().to_string() == "IEND")
+        {
+            Some(index) => self.chunks.insert(index, chunk),
+            None => self.chunks.push(chunk),
+        }
+    }
+
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, Error> {
         for i in 0..self.chunks.len() {
             if self.chunks[i].chunk_type().to_string() == chunk_type {
                 return Ok(self.chunks.remove(i));
             }
         }
-        return Err(Error::from("can not find"));
+        Err(Error::from("can not find"))
+    }
+
+    /// replace the first chunk of the same type in place
+    pub fn replace_chunk(&mut self, chunk: Chunk) -> Result<(), Error> {
+        let target = self
+            .chunks
+            .iter_mut()
+            .find(|old| old.chunk_type() == chunk.chunk_type())
+            .ok_or_else(|| Error::from(format!("can not find the {} chunk", chunk.chunk_type())))?;
+        *target = chunk;
+        Ok(())
+    }
+
+    /// replace the first chunk of the same type, or insert it before the first chunk of one of
+    /// the `before` types (IEND when none is found), for chunks the spec orders
+    pub fn set_chunk_before(&mut self, chunk: Chunk, before: &[&str]) {
+        if let Some(old) = self
+            .chunks
+            .iter_mut()
+            .find(|old| old.chunk_type() == chunk.chunk_type())
+        {
+            *old = chunk;
+            return;
+        }
+        match self.chunks.iter().position(|old| {
+            let old_type = old.chunk_type().to_string();
+            before.contains(&old_type.as_str())
+        }) {
+            Some(index) => self.chunks.insert(index, chunk),
+            None => self.insert_before_iend(chunk),
+        }
+    }
+
+    /// remove every chunk of the type whose data matches the predicate, returns how many were removed
+    pub fn remove_chunks_where<F: Fn(&Chunk) -> bool>(&mut self, chunk_type: &str, f: F) -> usize {
+        let before = self.chunks.len();
+        self.chunks
+            .retain(|chunk| !(chunk.chunk_type().to_string() == chunk_type && f(chunk)));
+        before - self.chunks.len()
+    }
+
+    /// replace the IDAT chunks with the new compressed stream, split into chunks of at most
+    /// `max_len` bytes and placed where the first IDAT was. an empty stream keeps one empty IDAT
+    pub fn replace_idat(&mut self, stream: &[u8], max_len: usize) -> Result<(), Error> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type().to_string() == "IDAT")
+            .ok_or_else(|| Error::from("missing IDAT chunk"))?;
+        self.chunks
+            .retain(|chunk| chunk.chunk_type().to_string() != "IDAT");
+        let idat_type: ChunkType = "IDAT".parse().unwrap();
+        for (i, part) in stream
+            .chunks(max_len.clamp(1, Chunk::MAX_LENGTH))
+            .enumerate()
+        {
+            self.chunks
+                .insert(index + i, Chunk::new(idat_type.clone(), part.to_vec()));
+        }
+        if stream.is_empty() {
+            self.chunks.insert(index, Chunk::new(idat_type, Vec::new()));
+        }
+        Ok(())
+    }
+
+    /// the compressed image stream, the IDAT payloads concatenated in file order
+    pub fn idat_stream(&self) -> Vec<u8> {
+        self.chunks_by_type("IDAT")
+            .iter()
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect()
+    }
+
+    /// split the compressed stream again into IDAT chunks of at most `max_len` bytes, without
+    /// inflating it
+    pub fn rechunk_idat(&mut self, max_len: usize) -> Result<(), Error> {
+        if max_len == 0 {
+            return Err(Error::from("IDAT chunks must hold at least 1 byte"));
+        }
+        let stream = self.idat_stream();
+        self.replace_idat(&stream, max_len)
+    }
+
+    /// join the IDAT chunks into one, where the first one was
+    pub fn merge_idat(&mut self) -> Result<(), Error> {
+        self.rechunk_idat(Chunk::MAX_LENGTH)
+    }
+
+    /// the RGB entries of PLTE, checked against the color type and bit depth of IHDR
+    pub fn palette(&self) -> Result<Option<Vec<[u8; 3]>>, Error> {
+        let header = Header::from_png(self)?;
+        let Some(chunk) = self.chunk_by_type("PLTE") else {
+            if header.color_type == ColorType::Indexed {
+                return Err(Error::from("indexed color image without a PLTE chunk"));
+            }
+            return Ok(None);
+        };
+        let data = chunk.data();
+        if data.is_empty() || data.len() % 3 != 0 {
+            return Err(Error::from(format!(
+                "PLTE of {} bytes is not a whole number of entries",
+                data.len()
+            )));
+        }
+        let max_entries = match header.color_type {
+            ColorType::Indexed => 1 << header.bit_depth,
+            ColorType::Rgb | ColorType::Rgba => 256,
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => {
+                return Err(Error::from("PLTE is not allowed in grayscale images"))
+            }
+        };
+        if data.len() / 3 > max_entries {
+            return Err(Error::from(format!(
+                "PLTE has {} entries, bit depth {} allows {}",
+                data.len() / 3,
+                header.bit_depth,
+                max_entries
+            )));
+        }
+        Ok(Some(
+            data.chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect(),
+        ))
+    }
+
+    /// the tRNS chunk in the form the color type of IHDR calls for
+    pub fn transparency(&self) -> Result<Option<Transparency>, Error> {
+        let header = Header::from_png(self)?;
+        let transparency = Transparency::from_png(self, header.color_type)?;
+        if let Some(Transparency::PaletteAlpha(alpha)) = &transparency {
+            let entries = self.palette()?.map_or(0, |palette| palette.len());
+            if alpha.len() > entries {
+                return Err(Error::from(format!(
+                    "tRNS has {} alpha values for {} palette entries",
+                    alpha.len(),
+                    entries
+                )));
+            }
+        }
+        Ok(transparency)
+    }
+
+    /// the largest payload in bytes the method can store in this png, see
+    /// `stego::storage_capacity`
+    pub fn capacity(&self, method: &StorageMethod) -> Result<usize, Error> {
+        stego::storage_capacity(self, method)
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -51,21 +218,66 @@ impl Png {
     }
 }
 
-impl TryFrom<&[u8]> for Png {
-    type Error = &'static str;
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        if &value[0..8] != Png::STANDARD_HEADER {
-            return Err("File header is wrong!");
+impl Png {
+    /// the chunks that could be read and what is wrong with the file. a chunk whose CRC does not
+    /// match is kept, a truncated one ends the file. only a wrong signature is an error.
+    pub fn parse(value: &[u8]) -> Result<(Png, Vec<String>), Error> {
+        if value.len() < 8 || value[0..8] != Png::STANDARD_HEADER {
+            return Err(Error::from("File header is wrong!"));
         }
         let mut pivot: usize = 8;
-        let mut chunks = Vec::new();
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut problems = Vec::new();
         while pivot < value.len() {
+            let rest = value.len() - pivot;
+            if rest < 12 {
+                problems.push(format!(
+                    "truncated at byte {}: {} bytes left, a chunk needs at least 12",
+                    value.len(),
+                    rest
+                ));
+                break;
+            }
             let length = u32::from_be_bytes(value[pivot..pivot + 4].try_into().unwrap()) as usize;
+            let chunk_type = String::from_utf8_lossy(&value[pivot + 4..pivot + 8]).into_owned();
+            if length > Chunk::MAX_LENGTH || length + 12 > rest {
+                problems.push(format!(
+                    "truncated at byte {}: the {} chunk at byte {} needs {} bytes",
+                    value.len(),
+                    chunk_type,
+                    pivot,
+                    length.saturating_add(12)
+                ));
+                break;
+            }
             let offset = length + 12;
-            chunks.push(value[pivot..pivot + offset].try_into().unwrap());
+            let (chunk, stored) = Chunk::parse(&value[pivot..pivot + offset])
+                .map_err(|e| Error::from(format!("{} at byte {}", e, pivot)))?;
+            if chunk.crc() != stored {
+                let index = chunks
+                    .iter()
+                    .filter(|other| other.chunk_type() == chunk.chunk_type())
+                    .count();
+                problems.push(format!(
+                    "CRC mismatch in {} {} at byte {}",
+                    chunk_type, index, pivot
+                ));
+            }
+            chunks.push(chunk);
             pivot += offset;
         }
-        Ok(Png::from_chunks(chunks))
+        Ok((Png::from_chunks(chunks), problems))
+    }
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let (png, problems) = Png::parse(value)?;
+        match problems.into_iter().next() {
+            Some(problem) => Err(Error::from(problem)),
+            None => Ok(png),
+        }
     }
 }
 
@@ -76,13 +288,13 @@ impl fmt::Display for Png {
 }
 
 #[cfg(test)]
-mod tests {
+#[allow(clippy::vec_init_then_push, clippy::iter_cloned_collect)]
+pub(crate) mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
     use crate::Error;
     use std::convert::TryFrom;
-    use std::str::FromStr;
 
     fn testing_chunks() -> Vec<Chunk> {
         let mut chunks = Vec::new();
@@ -198,6 +410,224 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "second").unwrap());
+        let chunks = png.chunks_by_type("miDl");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(&chunks[1].data_as_string().unwrap(), "second");
+    }
+
+    #[test]
+    fn test_remove_chunks_where() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "second").unwrap());
+        let removed = png.remove_chunks_where("miDl", |chunk| chunk.data() != b"second");
+        assert_eq!(removed, 1);
+        assert_eq!(png.chunks_by_type("miDl").len(), 1);
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_replace_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let stream: Vec<u8> = (0..250).collect();
+        png.replace_idat(&stream, 100).unwrap();
+        let idats = png.chunks_by_type("IDAT");
+        assert_eq!(idats.len(), 3);
+        assert_eq!(idats[2].data().len(), 50);
+        let joined: Vec<u8> = idats
+            .iter()
+            .flat_map(|chunk| chunk.data().to_vec())
+            .collect();
+        assert_eq!(joined, stream);
+        assert_eq!(&png.chunks()[4].chunk_type().to_string(), "IDAT");
+        assert!(testing_png().replace_idat(&stream, 100).is_err());
+    }
+
+    #[test]
+    fn test_rechunk_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let stream = png.idat_stream();
+        let types = |png: &Png| -> Vec<String> {
+            png.chunks()
+                .iter()
+                .map(|chunk| chunk.chunk_type().to_string())
+                .filter(|chunk_type| chunk_type != "IDAT")
+                .collect()
+        };
+        let others = types(&png);
+        png.rechunk_idat(1000).unwrap();
+        let idats = png.chunks_by_type("IDAT");
+        assert_eq!(idats.len(), stream.len().div_ceil(1000));
+        assert!(idats.iter().all(|chunk| chunk.length() <= 1000));
+        assert_eq!(png.idat_stream(), stream);
+        assert_eq!(types(&png), others);
+        png.merge_idat().unwrap();
+        assert_eq!(png.chunks_by_type("IDAT").len(), 1);
+        assert_eq!(png.idat_stream(), stream);
+        assert!(png.rechunk_idat(0).is_err());
+        assert!(testing_png().merge_idat().is_err());
+
+        // an empty stream stays where the IDAT was, before the chunks that followed it
+        png.replace_idat(&[], 1000).unwrap();
+        let all_types = |png: &Png| -> Vec<String> {
+            png.chunks()
+                .iter()
+                .map(|chunk| chunk.chunk_type().to_string())
+                .collect()
+        };
+        let before = all_types(&png);
+        png.rechunk_idat(1000).unwrap();
+        assert_eq!(all_types(&png), before);
+        assert_eq!(png.idat_stream(), []);
+    }
+
+    pub(crate) fn image_png(color_type: ColorType, bit_depth: u8, extra: &[(&str, &[u8])]) -> Png {
+        let header = Header {
+            width: 1,
+            height: 1,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        let mut chunks = vec![Chunk::new("IHDR".parse().unwrap(), header.to_bytes())];
+        chunks.extend(
+            extra
+                .iter()
+                .map(|(chunk_type, data)| Chunk::new(chunk_type.parse().unwrap(), data.to_vec())),
+        );
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_palette() {
+        let plte: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let png = image_png(ColorType::Indexed, 2, &[("PLTE", plte)]);
+        assert_eq!(
+            png.palette().unwrap().unwrap(),
+            vec![[1, 2, 3], [4, 5, 6], [7, 8, 9]]
+        );
+        assert!(image_png(ColorType::Indexed, 1, &[("PLTE", plte)])
+            .palette()
+            .is_err());
+        assert!(image_png(ColorType::Indexed, 8, &[("PLTE", &plte[..8])])
+            .palette()
+            .is_err());
+        assert!(image_png(ColorType::Indexed, 8, &[]).palette().is_err());
+        assert!(image_png(ColorType::Grayscale, 8, &[("PLTE", plte)])
+            .palette()
+            .is_err());
+        assert_eq!(image_png(ColorType::Rgb, 8, &[]).palette().unwrap(), None);
+    }
+
+    #[test]
+    fn test_transparency() {
+        let plte: &[u8] = &[1, 2, 3, 4, 5, 6];
+        let png = image_png(ColorType::Indexed, 8, &[("PLTE", plte), ("tRNS", &[0])]);
+        assert_eq!(
+            png.transparency().unwrap(),
+            Some(Transparency::PaletteAlpha(vec![0]))
+        );
+        let png = image_png(
+            ColorType::Indexed,
+            8,
+            &[("PLTE", plte), ("tRNS", &[0, 1, 2])],
+        );
+        assert!(png.transparency().is_err());
+
+        let png = image_png(ColorType::Grayscale, 16, &[("tRNS", &[1, 2])]);
+        assert_eq!(
+            png.transparency().unwrap(),
+            Some(Transparency::GrayKey(0x0102))
+        );
+        let png = image_png(ColorType::Rgb, 8, &[("tRNS", &[0, 1, 0, 2, 0, 3])]);
+        assert_eq!(
+            png.transparency().unwrap(),
+            Some(Transparency::RgbKey(1, 2, 3))
+        );
+        assert!(image_png(ColorType::Rgba, 8, &[("tRNS", &[0, 1])])
+            .transparency()
+            .is_err());
+        assert_eq!(
+            image_png(ColorType::Rgb, 8, &[]).transparency().unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_replace_chunk() {
+        let mut png = testing_png();
+        png.replace_chunk(chunk_from_strings("FrSt", "replaced").unwrap())
+            .unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.chunks()[0].data_as_string().unwrap(), "replaced");
+        assert!(png
+            .replace_chunk(chunk_from_strings("NoNe", "missing").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_set_chunk_before() {
+        let mut png = testing_png();
+        png.set_chunk_before(
+            chunk_from_strings("orDr", "new").unwrap(),
+            &["miDl", "LASt"],
+        );
+        assert_eq!(png.chunks()[1].chunk_type().to_string(), "orDr");
+        png.set_chunk_before(chunk_from_strings("orDr", "newer").unwrap(), &["FrSt"]);
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(png.chunks()[1].data_as_string().unwrap(), "newer");
+        png.set_chunk_before(chunk_from_strings("laTe", "late").unwrap(), &["IDAT"]);
+        assert_eq!(png.chunks()[4].chunk_type().to_string(), "laTe");
+    }
+
+    #[test]
+    fn test_capacity() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let chunk = StorageMethod::Chunk { split_size: None };
+        assert_eq!(png.capacity(&chunk).unwrap(), Chunk::MAX_LENGTH);
+        let split = StorageMethod::Chunk {
+            split_size: Some(1020),
+        };
+        assert_eq!(png.capacity(&split).unwrap(), 1000 * u32::MAX as usize);
+        assert!(png.capacity(&StorageMethod::Itxt).unwrap() < Chunk::MAX_LENGTH);
+
+        // the dice are 50x50 RGBA with transparent corners
+        let lsb = |bits| {
+            png.capacity(&StorageMethod::Lsb(stego::LsbOptions {
+                bits,
+                ..Default::default()
+            }))
+            .unwrap()
+        };
+        assert!(lsb(1) > 0 && lsb(1) < 50 * 50 * 3 / 8);
+        assert!(lsb(2) > lsb(1) * 2);
+        let everything = StorageMethod::Lsb(stego::LsbOptions {
+            include_alpha: true,
+            include_transparent: true,
+            ..Default::default()
+        });
+        assert_eq!(png.capacity(&everything).unwrap(), 50 * 50 * 4 / 8 - 4);
+    }
+
+    #[test]
+    fn test_insert_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before_iend(chunk_from_strings("TeSt", "Message").unwrap());
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(&types[types.len() - 2..], ["TeSt", "IEND"]);
+
+        let mut png = testing_png();
+        png.insert_before_iend(chunk_from_strings("TeSt", "Message").unwrap());
+        assert_eq!(&png.chunks()[3].chunk_type().to_string(), "TeSt");
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -240,7 +670,7 @@ mod tests {
     }
 
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
-    const PNG_FILE: [u8; 4803] = [
+    pub(crate) const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
         6, 0, 0, 0, 30, 63, 136, 177, 0, 0, 0, 1, 115, 82, 71, 66, 0, 174, 206, 28, 233, 0, 0, 0,
         4, 103, 65, 77, 65, 0, 0, 177, 143, 11, 252, 97, 5, 0, 0, 0, 9, 112, 72, 89, 115, 0, 0, 14,
@@ -0,0 +1,356 @@
+/// Ed25519 signatures over the chunks of a png.
+///
+/// the signature chunk keeps a manifest of the covered chunks (type and SHA-256 of
+/// `Chunk::as_bytes`) in file order, and the signature is computed over the manifest,
+/// so `verify` can tell which chunks were modified or moved.
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    crypto::{parse_key, to_hex},
+    png::Png,
+    Error, Result,
+};
+
+/// ancillary, private, unsafe to copy: editors unaware of it must drop it when changing critical chunks
+pub const SIGNATURE_CHUNK_TYPE: &str = "siGN";
+
+const SIGNING_KEY_PREFIX: &str = "png-info-signing-key:";
+const VERIFYING_KEY_PREFIX: &str = "png-info-verifying-key:";
+/// prefixed to the manifest before signing, it also versions the format
+const CONTEXT: &[u8] = b"png_info signature v1";
+const DIGEST_LEN: usize = 32;
+const ENTRY_LEN: usize = 4 + DIGEST_LEN;
+const SIGNATURE_LEN: usize = 64;
+
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+pub fn signing_key_to_string(key: &SigningKey) -> String {
+    format!("{} {}\n", SIGNING_KEY_PREFIX, to_hex(&key.to_bytes()))
+}
+
+pub fn signing_key_from_string(s: &str) -> Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&parse_key(s, SIGNING_KEY_PREFIX)?))
+}
+
+pub fn verifying_key_to_string(key: &VerifyingKey) -> String {
+    format!("{} {}\n", VERIFYING_KEY_PREFIX, to_hex(key.as_bytes()))
+}
+
+pub fn verifying_key_from_string(s: &str) -> Result<VerifyingKey> {
+    Ok(VerifyingKey::from_bytes(&parse_key(
+        s,
+        VERIFYING_KEY_PREFIX,
+    )?)?)
+}
+
+/// the chunks covered by default: every critical chunk
+fn is_covered(chunk: &Chunk, extra: &[ChunkType]) -> bool {
+    let chunk_type = chunk.chunk_type();
+    chunk_type.to_string() != SIGNATURE_CHUNK_TYPE
+        && (chunk_type.is_critical() || extra.contains(chunk_type))
+}
+
+fn digest(chunk: &Chunk) -> [u8; DIGEST_LEN] {
+    Sha256::digest(chunk.as_bytes()).into()
+}
+
+/// build the signature chunk over the critical chunks and the extra ancillary chunk types
+pub fn sign(png: &Png, key: &SigningKey, extra: &[ChunkType]) -> Chunk {
+    let entries: Vec<u8> = png
+        .chunks()
+        .iter()
+        .filter(|chunk| is_covered(chunk, extra))
+        .flat_map(|chunk| [chunk.chunk_type().bytes().to_vec(), digest(chunk).to_vec()].concat())
+        .collect();
+    let manifest = [
+        key.verifying_key().as_bytes().as_ref(),
+        ((entries.len() / ENTRY_LEN) as u32).to_be_bytes().as_ref(),
+        entries.as_slice(),
+    ]
+    .concat();
+    let signature = key.sign(&[CONTEXT, manifest.as_slice()].concat());
+
+    Chunk::new(
+        ChunkType::from_str(SIGNATURE_CHUNK_TYPE).unwrap(),
+        [manifest, signature.to_bytes().to_vec()].concat(),
+    )
+}
+
+/// state of one covered chunk compared with the signed manifest
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkStatus {
+    Intact,
+    Modified,
+    /// signed but no longer in the file
+    Missing,
+    /// of a covered type but not in the signed manifest
+    Added,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub signer: VerifyingKey,
+    /// whether the manifest itself carries a valid signature
+    pub signature_valid: bool,
+    /// covered chunks in file order, grouped by type
+    pub chunks: Vec<(String, ChunkStatus)>,
+    /// covered chunks of different types were moved relative to each other, like PLTE after
+    /// IDAT. only types with as many chunks as signed are compared, the others are reported
+    /// as added or missing
+    pub reordered: bool,
+}
+
+impl Report {
+    /// the signature is valid and no covered chunk changed
+    pub fn is_intact(&self) -> bool {
+        self.signature_valid
+            && !self.reordered
+            && self
+                .chunks
+                .iter()
+                .all(|(_, status)| *status == ChunkStatus::Intact)
+    }
+}
+
+/// check the signature chunk of the png against its current chunks
+pub fn verify(png: &Png) -> Result<Report> {
+    let data = png
+        .chunk_by_type(SIGNATURE_CHUNK_TYPE)
+        .ok_or_else(|| Error::from("the file is not signed"))?
+        .data();
+    if data.len() < 32 + 4 + SIGNATURE_LEN {
+        return Err(Error::from("signature chunk is truncated"));
+    }
+    let signer = VerifyingKey::from_bytes(data[0..32].try_into().unwrap())?;
+    let count = u32::from_be_bytes(data[32..36].try_into().unwrap()) as usize;
+    let manifest_len = 36 + count * ENTRY_LEN;
+    if data.len() != manifest_len + SIGNATURE_LEN {
+        return Err(Error::from("signature chunk has a wrong length"));
+    }
+    let manifest = &data[..manifest_len];
+    let signature = ed25519_dalek::Signature::from_bytes(data[manifest_len..].try_into().unwrap());
+    let signature_valid = signer
+        .verify(&[CONTEXT, manifest].concat(), &signature)
+        .is_ok();
+
+    let entries: Vec<(ChunkType, &[u8])> = manifest[36..]
+        .chunks(ENTRY_LEN)
+        .map(|entry| {
+            let chunk_type: [u8; 4] = entry[0..4].try_into().unwrap();
+            Ok((ChunkType::try_from(chunk_type)?, &entry[4..]))
+        })
+        .collect::<std::result::Result<_, crate::chunk_type::ChunkTypeError>>()
+        .map_err(|e| Error::from(e.to_string()))?;
+
+    let mut covered_types: Vec<&ChunkType> = Vec::new();
+    for (chunk_type, _) in &entries {
+        if !covered_types.contains(&chunk_type) {
+            covered_types.push(chunk_type);
+        }
+    }
+
+    let mut chunks = Vec::new();
+    for chunk_type in covered_types {
+        let signed = entries.iter().filter(|(t, _)| t == chunk_type);
+        let current: Vec<&Chunk> = png
+            .chunks()
+            .iter()
+            .filter(|chunk| chunk.chunk_type() == chunk_type)
+            .collect();
+        let mut current = current.into_iter();
+        for (_, expected) in signed {
+            let status = match current.next() {
+                Some(chunk) if digest(chunk) == *expected => ChunkStatus::Intact,
+                Some(_) => ChunkStatus::Modified,
+                None => ChunkStatus::Missing,
+            };
+            chunks.push((chunk_type.to_string(), status));
+        }
+        chunks.extend(current.map(|chunk| (chunk.chunk_type().to_string(), ChunkStatus::Added)));
+    }
+    // critical chunks that were added with a type the manifest does not mention at all
+    for chunk in png.chunks() {
+        if is_covered(chunk, &[]) && !entries.iter().any(|(t, _)| t == chunk.chunk_type()) {
+            chunks.push((chunk.chunk_type().to_string(), ChunkStatus::Added));
+        }
+    }
+
+    // the manifest is in file order, compare the sequence of types as well
+    let current: Vec<&ChunkType> = png
+        .chunks()
+        .iter()
+        .filter(|chunk| {
+            is_covered(chunk, &[]) || entries.iter().any(|(t, _)| t == chunk.chunk_type())
+        })
+        .map(|chunk| chunk.chunk_type())
+        .collect();
+    let signed: Vec<&ChunkType> = entries.iter().map(|(t, _)| t).collect();
+    let same_count = |chunk_type: &&ChunkType| {
+        let count = |types: &[&ChunkType]| types.iter().filter(|t| *t == chunk_type).count();
+        count(&current) == count(&signed)
+    };
+    let reordered = !current
+        .iter()
+        .copied()
+        .filter(same_count)
+        .eq(signed.iter().copied().filter(same_count));
+
+    Ok(Report {
+        signer,
+        signature_valid,
+        chunks,
+        reordered,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(chunk_type).unwrap(),
+            data.as_bytes().to_vec(),
+        )
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header"),
+            chunk_from_strings("tEXt", "Comment\0hello"),
+            chunk_from_strings("IDAT", "first"),
+            chunk_from_strings("IDAT", "second"),
+            chunk_from_strings("IEND", ""),
+        ])
+    }
+
+    fn signed_png(key: &SigningKey, extra: &[ChunkType]) -> Png {
+        let mut png = testing_png();
+        let signature = sign(&png, key, extra);
+        png.insert_before_iend(signature);
+        png
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = generate_signing_key();
+        let png = signed_png(&key, &[]);
+        let report = verify(&png).unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.signer, key.verifying_key());
+        let covered: Vec<&str> = report.chunks.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(covered, ["IHDR", "IDAT", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_modified_chunk() {
+        let key = generate_signing_key();
+        let png = signed_png(&key, &[]);
+        let mut chunks: Vec<Chunk> = png
+            .chunks()
+            .iter()
+            .map(|chunk| Chunk::try_from(chunk.as_bytes().as_ref()).unwrap())
+            .collect();
+        chunks[3] = chunk_from_strings("IDAT", "tampered");
+        let report = verify(&Png::from_chunks(chunks)).unwrap();
+        assert!(report.signature_valid);
+        assert!(!report.is_intact());
+        assert_eq!(
+            report.chunks[2],
+            ("IDAT".to_string(), ChunkStatus::Modified)
+        );
+    }
+
+    #[test]
+    fn test_uncovered_chunk_may_change() {
+        let key = generate_signing_key();
+        let mut png = signed_png(&key, &[]);
+        png.remove_chunk("tEXt").unwrap();
+        assert!(verify(&png).unwrap().is_intact());
+
+        let mut png = signed_png(&key, &[ChunkType::from_str("tEXt").unwrap()]);
+        png.remove_chunk("tEXt").unwrap();
+        let report = verify(&png).unwrap();
+        assert!(!report.is_intact());
+        assert!(report
+            .chunks
+            .contains(&("tEXt".to_string(), ChunkStatus::Missing)));
+    }
+
+    #[test]
+    fn test_reordered_chunks() {
+        let key = generate_signing_key();
+        let png = signed_png(&key, &[]);
+        let moved = |from: usize, to: usize| {
+            let mut chunks: Vec<Chunk> = png
+                .chunks()
+                .iter()
+                .map(|chunk| Chunk::try_from(chunk.as_bytes().as_ref()).unwrap())
+                .collect();
+            let chunk = chunks.remove(from);
+            chunks.insert(to, chunk);
+            verify(&Png::from_chunks(chunks)).unwrap()
+        };
+        // IDAT behind IEND
+        let report = moved(3, 5);
+        assert!(report.reordered);
+        assert!(!report.is_intact());
+        // the uncovered tEXt may move
+        let report = moved(1, 3);
+        assert!(!report.reordered);
+        assert!(report.is_intact());
+
+        let mut png = signed_png(&key, &[]);
+        png.remove_chunk("IHDR").unwrap();
+        png.insert_before_iend(chunk_from_strings("IHDR", "header"));
+        assert!(verify(&png).unwrap().reordered);
+    }
+
+    #[test]
+    fn test_added_critical_chunk() {
+        let key = generate_signing_key();
+        let mut png = signed_png(&key, &[]);
+        png.insert_before_iend(chunk_from_strings("PLTE", "palette"));
+        let report = verify(&png).unwrap();
+        assert!(report
+            .chunks
+            .contains(&("PLTE".to_string(), ChunkStatus::Added)));
+    }
+
+    #[test]
+    fn test_forged_manifest() {
+        let key = generate_signing_key();
+        let png = signed_png(&key, &[]);
+        let mut data = png
+            .chunk_by_type(SIGNATURE_CHUNK_TYPE)
+            .unwrap()
+            .data()
+            .to_vec();
+        data[40] ^= 1;
+        let mut png = testing_png();
+        png.insert_before_iend(Chunk::new(
+            ChunkType::from_str(SIGNATURE_CHUNK_TYPE).unwrap(),
+            data,
+        ));
+        assert!(!verify(&png).unwrap().signature_valid);
+    }
+
+    #[test]
+    fn test_key_strings() {
+        let key = generate_signing_key();
+        let restored = signing_key_from_string(&signing_key_to_string(&key)).unwrap();
+        assert_eq!(restored.verifying_key(), key.verifying_key());
+        let public = verifying_key_to_string(&key.verifying_key());
+        assert_eq!(
+            verifying_key_from_string(&public).unwrap(),
+            key.verifying_key()
+        );
+    }
+}
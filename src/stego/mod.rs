@@ -0,0 +1,607 @@
+/// hiding payloads in the least significant bits of the pixel samples.
+/// the payload is prefixed with its length (u32) and written MSB first, `bits` bits per
+/// selected sample, in scanline order of the IDAT passes or in an order shuffled by a seed.
+use rand_chacha::{
+    rand_core::{RngCore, SeedableRng},
+    ChaCha20Rng,
+};
+use std::{fmt, str::FromStr};
+
+use crate::{
+    chunk::Chunk,
+    image::{get_sample, set_sample, ColorType, Header, ImageData, Transparency},
+    payload::SEQUENCE_HEADER_LEN,
+    png::Png,
+    Error, Result,
+};
+
+pub mod palette;
+
+/// where `encode` hides the payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// in a dedicated chunk
+    Chunk,
+    /// in the least significant bits of the pixels
+    Lsb,
+    /// in the choice between twin entries of the palette
+    Palette,
+}
+
+impl FromStr for Method {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "chunk" => Ok(Method::Chunk),
+            "lsb" => Ok(Method::Lsb),
+            "palette" => Ok(Method::Palette),
+            _ => Err(Error::from(format!(
+                "unknown method `{}`, expected chunk, lsb or palette",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::Chunk => write!(f, "chunk"),
+            Method::Lsb => write!(f, "lsb"),
+            Method::Palette => write!(f, "palette"),
+        }
+    }
+}
+
+const LENGTH_BITS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsbOptions {
+    /// bits written into every selected sample, 1 to 8
+    pub bits: u8,
+    /// channel letters: `rgba` for color, `ya` for grayscale, `i` to read palette images.
+    /// every channel but alpha when empty
+    pub channels: String,
+    /// allow writing into the alpha channel
+    pub include_alpha: bool,
+    /// also use pixels whose alpha is zero
+    pub include_transparent: bool,
+    /// visit the samples in an order derived from this seed instead of scanline order
+    pub seed: Option<[u8; 32]>,
+}
+
+/// context of `crypto::derive_seed` for the visiting order
+pub const ORDER_SEED_CONTEXT: &str = "png_info lsb visiting order";
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions {
+            bits: 1,
+            channels: String::new(),
+            include_alpha: false,
+            include_transparent: false,
+            seed: None,
+        }
+    }
+}
+
+impl LsbOptions {
+    /// indexes of the channels to write into
+    fn channel_indexes(&self, header: &Header) -> Result<Vec<usize>> {
+        let names = match header.color_type {
+            ColorType::Grayscale => "y",
+            ColorType::GrayscaleAlpha => "ya",
+            ColorType::Rgb => "rgb",
+            ColorType::Rgba => "rgba",
+            ColorType::Indexed => "i",
+        };
+        let alpha = header.color_type.has_alpha().then(|| names.len() - 1);
+        let indexes: Vec<usize> = if self.channels.is_empty() {
+            (0..names.len())
+                .filter(|i| self.include_alpha || Some(*i) != alpha)
+                .collect()
+        } else {
+            self.channels
+                .chars()
+                .map(|c| {
+                    names.find(c).ok_or_else(|| {
+                        Error::from(format!(
+                            "channel `{}` does not exist, the image has channels `{}`",
+                            c, names
+                        ))
+                    })
+                })
+                .collect::<Result<_>>()?
+        };
+        if !self.include_alpha && alpha.is_some_and(|alpha| indexes.contains(&alpha)) {
+            return Err(Error::from("writing into alpha needs --include-alpha"));
+        }
+        if indexes.is_empty() {
+            return Err(Error::from("no channel selected"));
+        }
+        Ok(indexes)
+    }
+
+    fn validate(&self, header: &Header) -> Result<()> {
+        if self.bits == 0 || self.bits > 8 || self.bits > header.bit_depth {
+            return Err(Error::from(format!(
+                "bits per channel must be between 1 and {}",
+                header.bit_depth.min(8)
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// one sample that carries payload bits
+#[derive(Debug, Clone, Copy)]
+pub struct Slot {
+    pass: usize,
+    /// byte offset of the scanline in the pass data
+    row: usize,
+    /// sample index in the scanline
+    sample: usize,
+}
+
+/// every sample that carries payload bits, in scanline order or shuffled by the seed
+pub fn slots(image: &ImageData, options: &LsbOptions) -> Result<Vec<Slot>> {
+    let header = &image.header;
+    options.validate(header)?;
+    let channels = options.channel_indexes(header)?;
+    let channel_count = header.channels();
+
+    let mut slots = Vec::new();
+    for (pass, sub) in image.passes.iter().enumerate() {
+        if sub.data.is_empty() {
+            continue;
+        }
+        let row_len = header.row_len(sub.width);
+        for y in 0..sub.height as usize {
+            let row = y * row_len;
+            for x in 0..sub.width as usize {
+                let first = x * channel_count;
+                if !options.include_transparent
+                    && is_transparent(image, &sub.data[row..row + row_len], first, options)
+                {
+                    continue;
+                }
+                slots.extend(channels.iter().map(|channel| Slot {
+                    pass,
+                    row,
+                    sample: first + channel,
+                }));
+            }
+        }
+    }
+    if let Some(seed) = options.seed {
+        shuffle(&mut slots, seed);
+    }
+    Ok(slots)
+}
+
+/// whether the pixel starting at sample `first` is fully transparent, by its alpha or tRNS.
+/// the bits the embedding may change are masked off, so the answer stays the same after it.
+fn is_transparent(image: &ImageData, row: &[u8], first: usize, options: &LsbOptions) -> bool {
+    let header = &image.header;
+    let low_bits = (1u16 << options.bits) - 1;
+    let sample = |i: usize| get_sample(row, first + i, header.bit_depth);
+    if header.color_type.has_alpha() {
+        let alpha = sample(header.channels() - 1);
+        let mask = if options.include_alpha {
+            !low_bits
+        } else {
+            u16::MAX
+        };
+        return alpha & mask == 0;
+    }
+    match &image.transparency {
+        Some(Transparency::GrayKey(gray)) => sample(0) & !low_bits == gray & !low_bits,
+        Some(Transparency::RgbKey(r, g, b)) => [*r, *g, *b]
+            .iter()
+            .enumerate()
+            .all(|(i, key)| sample(i) & !low_bits == key & !low_bits),
+        Some(Transparency::PaletteAlpha(alpha)) => {
+            // any palette entry the index can turn into
+            let group = sample(0) & !low_bits;
+            (group..=group | low_bits)
+                .any(|index| alpha.get(index as usize).is_some_and(|alpha| *alpha == 0))
+        }
+        None => false,
+    }
+}
+
+/// Fisher-Yates shuffle driven by ChaCha20 seeded with `seed`
+fn shuffle<T>(items: &mut [T], seed: [u8; 32]) {
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    for i in (1..items.len()).rev() {
+        // rejection sampling keeps the index uniform
+        let bound = i as u64 + 1;
+        let zone = u64::MAX - u64::MAX % bound;
+        let j = loop {
+            let value = rng.next_u64();
+            if value < zone {
+                break value % bound;
+            }
+        };
+        items.swap(i, j as usize);
+    }
+}
+
+/// bytes of payload that fit into the slots, besides the length prefix
+pub fn capacity_of(slots: usize, bits: u8) -> usize {
+    (slots * bits as usize).saturating_sub(LENGTH_BITS) / 8
+}
+
+/// changing the low bits of a palette index can point it past the last PLTE entry
+fn check_writable(header: &Header) -> Result<()> {
+    if header.color_type == ColorType::Indexed {
+        return Err(Error::from(
+            "the lsb method can turn palette indexes into entries PLTE does not have, use the palette method for indexed images",
+        ));
+    }
+    Ok(())
+}
+
+/// largest payload `embed` accepts with these options
+pub fn capacity(image: &ImageData, options: &LsbOptions) -> Result<usize> {
+    check_writable(&image.header)?;
+    Ok(capacity_of(slots(image, options)?.len(), options.bits))
+}
+
+/// where a payload can be stored, see `storage_capacity`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageMethod {
+    /// dedicated chunks of at most `split_size` bytes each, a single chunk when `None`
+    Chunk { split_size: Option<usize> },
+    /// the text of an iTXt chunk with the `Comment` keyword, it must be UTF-8.
+    /// `encode` can not write it yet
+    Itxt,
+    /// the least significant bits of the pixels
+    Lsb(LsbOptions),
+    /// twin palette entries of an indexed image
+    Palette,
+}
+
+/// keyword, its null separator, compression flag and method, empty language tag and
+/// translated keyword with their separators
+pub const ITXT_OVERHEAD: usize = "Comment".len() + 1 + 2 + 1 + 1;
+
+/// payload bytes each part holds when split into chunks of `split_size` bytes
+pub fn split_part_capacity(split_size: usize) -> Result<usize> {
+    let per_chunk = split_size.min(Chunk::MAX_LENGTH);
+    if per_chunk <= SEQUENCE_HEADER_LEN {
+        return Err(Error::from(
+            "the chunk size is smaller than the sequence header",
+        ));
+    }
+    Ok(per_chunk - SEQUENCE_HEADER_LEN)
+}
+
+/// the largest payload in bytes the method can store in the png. the headers `encode` adds for
+/// files, compression and encryption are part of the payload, they are not subtracted.
+/// split chunks are only bounded by the u32 part index, `split_part_capacity` is more telling
+pub fn storage_capacity(png: &Png, method: &StorageMethod) -> Result<usize> {
+    match method {
+        StorageMethod::Chunk { split_size: None } => Ok(Chunk::MAX_LENGTH),
+        StorageMethod::Chunk {
+            split_size: Some(split_size),
+        } => Ok(split_part_capacity(*split_size)?.saturating_mul(u32::MAX as usize)),
+        StorageMethod::Itxt => Ok(Chunk::MAX_LENGTH - ITXT_OVERHEAD),
+        StorageMethod::Lsb(options) => capacity(&ImageData::decode(png)?, options),
+        StorageMethod::Palette => palette::capacity(png),
+    }
+}
+
+/// write the payload into the samples
+pub fn embed(image: &mut ImageData, payload: &[u8], options: &LsbOptions) -> Result<()> {
+    check_writable(&image.header)?;
+    let slots = slots(image, options)?;
+    embed_in(image, &slots, payload, options.bits)
+}
+
+/// read a payload written by `embed` with the same options
+pub fn extract(image: &ImageData, options: &LsbOptions) -> Result<Vec<u8>> {
+    let slots = slots(image, options)?;
+    extract_from(image, &slots, options.bits)
+}
+
+fn embed_in(image: &mut ImageData, slots: &[Slot], payload: &[u8], bits: u8) -> Result<()> {
+    if payload.is_empty() {
+        return Err(Error::from("the payload is empty"));
+    }
+    let capacity = capacity_of(slots.len(), bits);
+    if payload.len() > capacity || payload.len() > u32::MAX as usize {
+        return Err(Error::from(format!(
+            "the payload of {} bytes exceeds the capacity of {} bytes",
+            payload.len(),
+            capacity
+        )));
+    }
+    let framed = [(payload.len() as u32).to_be_bytes().as_ref(), payload].concat();
+    let total_bits = framed.len() * 8;
+    let bit_depth = image.header.bit_depth;
+    let row_len = |image: &ImageData, pass: usize| image.header.row_len(image.passes[pass].width);
+
+    for (i, slot) in slots.iter().enumerate() {
+        let first_bit = i * bits as usize;
+        if first_bit >= total_bits {
+            break;
+        }
+        let mut value = 0u16;
+        for k in first_bit..first_bit + bits as usize {
+            let bit = if k < total_bits {
+                (framed[k / 8] >> (7 - k % 8)) & 1
+            } else {
+                0
+            };
+            value = (value << 1) | bit as u16;
+        }
+        let len = row_len(image, slot.pass);
+        let row = &mut image.passes[slot.pass].data[slot.row..slot.row + len];
+        let mask = (1u16 << bits) - 1;
+        let sample = get_sample(row, slot.sample, bit_depth);
+        set_sample(row, slot.sample, bit_depth, (sample & !mask) | value);
+    }
+    Ok(())
+}
+
+fn extract_from(image: &ImageData, slots: &[Slot], bits: u8) -> Result<Vec<u8>> {
+    let bit_depth = image.header.bit_depth;
+    let mask = (1u16 << bits) - 1;
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut accumulator = 0u32;
+    let mut pending = 0;
+    let mut expected: Option<usize> = None;
+
+    for slot in slots {
+        let sub = &image.passes[slot.pass];
+        let len = image.header.row_len(sub.width);
+        let value = get_sample(&sub.data[slot.row..slot.row + len], slot.sample, bit_depth) & mask;
+        accumulator = (accumulator << bits) | value as u32;
+        pending += bits as usize;
+        while pending >= 8 {
+            pending -= 8;
+            bytes.push((accumulator >> pending) as u8);
+            accumulator &= (1 << pending) - 1;
+        }
+        if expected.is_none() && bytes.len() >= 4 {
+            let length = u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as usize;
+            // `embed` never writes an empty payload, so zero means there is nothing
+            if length == 0 || length > capacity_of(slots.len(), bits) {
+                return Err(Error::from(
+                    "no hidden payload found in the pixels, check the options",
+                ));
+            }
+            expected = Some(length + 4);
+        }
+        if expected.is_some_and(|expected| bytes.len() >= expected) {
+            break;
+        }
+    }
+    match expected {
+        Some(expected) if bytes.len() >= expected => Ok(bytes[4..expected].to_vec()),
+        _ => Err(Error::from("no hidden payload found in the pixels")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::SubImage;
+
+    fn testing_image(color_type: ColorType, bit_depth: u8) -> ImageData {
+        let header = Header {
+            width: 20,
+            height: 10,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        let len = header.row_len(20) * 10;
+        ImageData {
+            header,
+            passes: vec![SubImage {
+                width: 20,
+                height: 10,
+                data: (0..len).map(|i| (i * 7 % 256) as u8 | 0x80).collect(),
+            }],
+            transparency: None,
+        }
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        for (color_type, bit_depth, bits) in [
+            (ColorType::Rgb, 8, 1),
+            (ColorType::Rgba, 8, 2),
+            (ColorType::Rgb, 16, 3),
+            (ColorType::Grayscale, 4, 4),
+        ] {
+            let mut image = testing_image(color_type, bit_depth);
+            let options = LsbOptions {
+                bits,
+                ..LsbOptions::default()
+            };
+            embed(&mut image, b"hidden in plain sight", &options).unwrap();
+            assert_eq!(extract(&image, &options).unwrap(), b"hidden in plain sight");
+        }
+    }
+
+    #[test]
+    fn test_only_low_bits_change() {
+        let original = testing_image(ColorType::Rgba, 8);
+        let mut image = original.clone();
+        embed(&mut image, &[0xff; 40], &LsbOptions::default()).unwrap();
+        let data = (&original.passes[0].data, &image.passes[0].data);
+        for (i, (before, after)) in data.0.iter().zip(data.1.iter()).enumerate() {
+            assert_eq!(before & !1, after & !1);
+            if i % 4 == 3 {
+                // alpha is skipped by default
+                assert_eq!(before, after);
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image = testing_image(ColorType::Rgb, 8);
+        let capacity =
+            |options: &LsbOptions| capacity_of(slots(&image, options).unwrap().len(), options.bits);
+        let options = LsbOptions::default();
+        assert_eq!(capacity(&options), (600 - 32) / 8);
+        let payload = vec![1u8; capacity(&options) + 1];
+        assert!(embed(&mut image.clone(), &payload, &options).is_err());
+        let options = LsbOptions {
+            channels: "g".to_string(),
+            bits: 2,
+            ..LsbOptions::default()
+        };
+        assert_eq!(capacity(&options), (400 - 32) / 8);
+    }
+
+    #[test]
+    fn test_skip_transparent_pixels() {
+        let mut image = testing_image(ColorType::Rgba, 8);
+        for alpha in image.passes[0].data.iter_mut().skip(3).step_by(8) {
+            *alpha = 0;
+        }
+        let options = LsbOptions::default();
+        assert_eq!(slots(&image, &options).unwrap().len(), 100 * 3);
+        let options = LsbOptions {
+            include_transparent: true,
+            ..LsbOptions::default()
+        };
+        assert_eq!(slots(&image, &options).unwrap().len(), 200 * 3);
+
+        // alpha carrying payload bits keeps the same pixels transparent
+        let options = LsbOptions {
+            include_alpha: true,
+            bits: 2,
+            ..LsbOptions::default()
+        };
+        embed(&mut image, &[0xff; 60], &options).unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), vec![0xff; 60]);
+    }
+
+    #[test]
+    fn test_skip_trns_pixels() {
+        let mut image = testing_image(ColorType::Rgb, 8);
+        image.passes[0].data[0..3].copy_from_slice(&[10, 20, 30]);
+        image.passes[0].data[3..6].copy_from_slice(&[11, 20, 31]);
+        image.transparency = Some(Transparency::RgbKey(10, 20, 30));
+        // both pixels match the key once the low bit is masked
+        assert_eq!(
+            slots(&image, &LsbOptions::default()).unwrap().len(),
+            198 * 3
+        );
+
+        let mut image = testing_image(ColorType::Indexed, 8);
+        let mut alpha = vec![255; 256];
+        let transparent = image.passes[0].data[0] ^ 1;
+        alpha[transparent as usize] = 0;
+        image.transparency = Some(Transparency::PaletteAlpha(alpha));
+        // indexes that differ from the transparent one in the low bit only are skipped too
+        let opaque = image.passes[0]
+            .data
+            .iter()
+            .filter(|index| **index >> 1 != transparent >> 1)
+            .count();
+        let options = LsbOptions::default();
+        let slots = slots(&image, &options).unwrap();
+        assert_eq!(slots.len(), opaque);
+        embed_in(&mut image, &slots, b"palette", options.bits).unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), b"palette");
+    }
+
+    #[test]
+    fn test_indexed_is_refused() {
+        // a 3 entry palette, index 2 becomes 3 when its low bit is set
+        let mut image = testing_image(ColorType::Indexed, 8);
+        for index in image.passes[0].data.iter_mut() {
+            *index = 2;
+        }
+        let error = embed(&mut image, &[0xff; 4], &LsbOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("palette method"), "{}", error);
+        assert!(image.passes[0].data.iter().all(|index| *index == 2));
+        assert!(capacity(&image, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let image = testing_image(ColorType::Rgba, 8);
+        let bad = [
+            LsbOptions {
+                bits: 0,
+                ..LsbOptions::default()
+            },
+            LsbOptions {
+                bits: 9,
+                ..LsbOptions::default()
+            },
+            LsbOptions {
+                channels: "ra".to_string(),
+                ..LsbOptions::default()
+            },
+            LsbOptions {
+                channels: "y".to_string(),
+                ..LsbOptions::default()
+            },
+        ];
+        for options in bad {
+            assert!(slots(&image, &options).is_err(), "{:?}", options);
+        }
+        let image = testing_image(ColorType::Grayscale, 2);
+        let options = LsbOptions {
+            bits: 3,
+            ..LsbOptions::default()
+        };
+        assert!(slots(&image, &options).is_err());
+    }
+
+    #[test]
+    fn test_shuffled_order() {
+        let mut image = testing_image(ColorType::Rgb, 8);
+        let options = LsbOptions {
+            seed: Some([7; 32]),
+            ..LsbOptions::default()
+        };
+        let shuffled = slots(&image, &options).unwrap();
+        let sequential = slots(&image, &LsbOptions::default()).unwrap();
+        assert_eq!(shuffled.len(), sequential.len());
+        let moved = shuffled
+            .iter()
+            .zip(sequential.iter())
+            .filter(|(a, b)| a.row != b.row || a.sample != b.sample)
+            .count();
+        assert!(moved > shuffled.len() / 2);
+
+        embed(&mut image, b"scattered", &options).unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), b"scattered");
+        let wrong_key = LsbOptions {
+            seed: Some([8; 32]),
+            ..LsbOptions::default()
+        };
+        assert_ne!(
+            extract(&image, &wrong_key).ok(),
+            Some(b"scattered".to_vec())
+        );
+        assert!(extract(&image, &LsbOptions::default()).ok() != Some(b"scattered".to_vec()));
+    }
+
+    #[test]
+    fn test_shuffle_is_a_permutation() {
+        let mut items: Vec<usize> = (0..1000).collect();
+        shuffle(&mut items, [1; 32]);
+        let mut again: Vec<usize> = (0..1000).collect();
+        shuffle(&mut again, [1; 32]);
+        assert_eq!(items, again);
+        items.sort();
+        assert_eq!(items, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_extract_without_payload() {
+        let image = testing_image(ColorType::Rgb, 8);
+        assert!(extract(&image, &LsbOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+/// hiding payloads in indexed images without touching the colors.
+/// every used palette color gets a twin entry with the same RGB and alpha, appended or
+/// written over an unused entry. each pixel of a color with a twin then carries one bit:
+/// pointing at the first entry of the pair is a 0, at the second a 1.
+use std::collections::HashMap;
+
+use super::{capacity_of, shuffle, Slot, LENGTH_BITS};
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    image::{get_sample, set_sample, ColorType, ImageData, Transparency},
+    metadata::Histogram,
+    png::Png,
+    Error, Result,
+};
+
+/// red, green, blue and alpha of one palette entry
+type Entry = [u8; 4];
+
+/// the palette of an indexed image with the alpha from tRNS
+fn read_palette(png: &Png, image: &ImageData) -> Result<Vec<Entry>> {
+    if image.header.color_type != ColorType::Indexed {
+        return Err(Error::from(
+            "the palette method needs an indexed color image",
+        ));
+    }
+    let palette = png
+        .palette()?
+        .ok_or_else(|| Error::from("missing PLTE chunk"))?;
+    let alpha = match &image.transparency {
+        Some(Transparency::PaletteAlpha(alpha)) => alpha.as_slice(),
+        _ => &[],
+    };
+    Ok(palette
+        .iter()
+        .enumerate()
+        .map(|(i, rgb)| [rgb[0], rgb[1], rgb[2], alpha.get(i).copied().unwrap_or(255)])
+        .collect())
+}
+
+/// how many pixels use each palette entry
+fn usage(image: &ImageData, entries: usize) -> Result<Vec<usize>> {
+    let mut counts = vec![0; entries];
+    for slot in pixels(image) {
+        let index = index_at(image, &slot) as usize;
+        *counts.get_mut(index).ok_or_else(|| {
+            Error::from(format!(
+                "pixel index {} is outside the palette of {} entries",
+                index, entries
+            ))
+        })? += 1;
+    }
+    Ok(counts)
+}
+
+/// for every entry that is the first or second one of its color, both indexes of the pair
+fn twins(entries: &[Entry]) -> Vec<Option<[usize; 2]>> {
+    let mut seen: HashMap<Entry, Vec<usize>> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        seen.entry(*entry).or_default().push(i);
+    }
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| match seen[entry].as_slice() {
+            [first, second, ..] if i == *first || i == *second => Some([*first, *second]),
+            _ => None,
+        })
+        .collect()
+}
+
+/// give the most used colors a twin, over unused entries first and then appended
+/// until the bit depth allows no more
+fn add_twins(
+    entries: &mut Vec<Entry>,
+    counts: &[usize],
+    max_entries: usize,
+    reserved: Option<usize>,
+) {
+    let paired = twins(entries);
+    let mut candidates: Vec<usize> = (0..entries.len())
+        .filter(|i| counts[*i] > 0 && paired[*i].is_none())
+        .collect();
+    candidates.sort_by_key(|i| std::cmp::Reverse(counts[*i]));
+    let mut free = (0..entries.len())
+        .filter(|i| counts[*i] == 0 && paired[*i].is_none() && Some(*i) != reserved)
+        .chain(entries.len()..max_entries);
+
+    for candidate in candidates {
+        let Some(position) = free.next() else {
+            break;
+        };
+        let entry = entries[candidate];
+        match entries.get_mut(position) {
+            Some(unused) => *unused = entry,
+            None => entries.push(entry),
+        }
+    }
+}
+
+/// every pixel, in scanline order of the passes
+fn pixels(image: &ImageData) -> impl Iterator<Item = Slot> + '_ {
+    image
+        .passes
+        .iter()
+        .enumerate()
+        .filter(|(_, sub)| !sub.data.is_empty())
+        .flat_map(move |(pass, sub)| {
+            let row_len = image.header.row_len(sub.width);
+            (0..sub.height as usize).flat_map(move |y| {
+                (0..sub.width as usize).map(move |x| Slot {
+                    pass,
+                    row: y * row_len,
+                    sample: x,
+                })
+            })
+        })
+}
+
+fn index_at(image: &ImageData, slot: &Slot) -> u16 {
+    let sub = &image.passes[slot.pass];
+    let len = image.header.row_len(sub.width);
+    get_sample(
+        &sub.data[slot.row..slot.row + len],
+        slot.sample,
+        image.header.bit_depth,
+    )
+}
+
+/// the pixels whose color has a twin, in scanline order or shuffled by the seed
+fn carriers(image: &ImageData, twins: &[Option<[usize; 2]>], seed: Option<[u8; 32]>) -> Vec<Slot> {
+    let mut slots: Vec<Slot> = pixels(image)
+        .filter(|slot| {
+            twins
+                .get(index_at(image, slot) as usize)
+                .is_some_and(|pair| pair.is_some())
+        })
+        .collect();
+    if let Some(seed) = seed {
+        shuffle(&mut slots, seed);
+    }
+    slots
+}
+
+/// the palette with twins that `embed` would produce
+fn prepare(png: &Png, image: &ImageData) -> Result<Vec<Entry>> {
+    let mut entries = read_palette(png, image)?;
+    let counts = usage(image, entries.len())?;
+    let max_entries = (1usize << image.header.bit_depth).min(256);
+    let background = png
+        .chunk_by_type("bKGD")
+        .and_then(|chunk| chunk.data().first())
+        .map(|index| *index as usize);
+    add_twins(&mut entries, &counts, max_entries, background);
+    Ok(entries)
+}
+
+/// largest payload `embed` accepts for the png
+pub fn capacity(png: &Png) -> Result<usize> {
+    let image = ImageData::decode(png)?;
+    let entries = prepare(png, &image)?;
+    Ok(capacity_of(
+        carriers(&image, &twins(&entries), None).len(),
+        1,
+    ))
+}
+
+/// add the twin entries and write the payload into the choice between them.
+/// PLTE, tRNS, hIST and IDAT of the png are rewritten.
+pub fn embed(png: &mut Png, payload: &[u8], seed: Option<[u8; 32]>) -> Result<()> {
+    if payload.is_empty() {
+        return Err(Error::from("the payload is empty"));
+    }
+    let mut image = ImageData::decode(png)?;
+    let entries = prepare(png, &image)?;
+    let twins = twins(&entries);
+    let slots = carriers(&image, &twins, seed);
+    let capacity = capacity_of(slots.len(), 1);
+    if payload.len() > capacity || payload.len() > u32::MAX as usize {
+        return Err(Error::from(format!(
+            "the payload of {} bytes exceeds the palette capacity of {} bytes",
+            payload.len(),
+            capacity
+        )));
+    }
+
+    // the pixels after the payload keep their entry, they are not read back
+    let framed = [(payload.len() as u32).to_be_bytes().as_ref(), payload].concat();
+    let bit_depth = image.header.bit_depth;
+    for (k, slot) in slots.iter().take(framed.len() * 8).enumerate() {
+        let bit = (framed[k / 8] >> (7 - k % 8)) & 1;
+        let pair = twins[index_at(&image, slot) as usize].unwrap();
+        let sub = &mut image.passes[slot.pass];
+        let len = image.header.row_len(sub.width);
+        set_sample(
+            &mut sub.data[slot.row..slot.row + len],
+            slot.sample,
+            bit_depth,
+            pair[bit as usize] as u16,
+        );
+    }
+    write_palette(png, &image, &entries)?;
+    image.write_to(png)
+}
+
+/// read a payload written by `embed` with the same seed
+pub fn extract(png: &Png, seed: Option<[u8; 32]>) -> Result<Vec<u8>> {
+    let image = ImageData::decode(png)?;
+    let entries = read_palette(png, &image)?;
+    let twins = twins(&entries);
+    let slots = carriers(&image, &twins, seed);
+    let not_found = || Error::from("no hidden payload found in the palette, check the options");
+
+    let mut bits = slots.iter().map(|slot| {
+        let index = index_at(&image, slot) as usize;
+        (twins[index].unwrap()[1] == index) as u8
+    });
+    let mut read_bytes = |count: usize| -> Option<Vec<u8>> {
+        (0..count)
+            .map(|_| (0..8).try_fold(0u8, |byte, _| Some((byte << 1) | bits.next()?)))
+            .collect()
+    };
+    let length = u32::from_be_bytes(
+        read_bytes(LENGTH_BITS / 8)
+            .ok_or_else(not_found)?
+            .try_into()
+            .unwrap(),
+    ) as usize;
+    // `embed` never writes an empty payload, so zero means there is nothing
+    if length == 0 || length > capacity_of(slots.len(), 1) {
+        return Err(not_found());
+    }
+    read_bytes(length).ok_or_else(not_found)
+}
+
+/// rewrite PLTE, the alpha of tRNS and the frequencies of hIST for the new entries
+fn write_palette(png: &mut Png, image: &ImageData, entries: &[Entry]) -> Result<()> {
+    let chunk_type = |name: &str| -> ChunkType { name.parse().unwrap() };
+    let plte: Vec<u8> = entries
+        .iter()
+        .flat_map(|entry| entry[..3].to_vec())
+        .collect();
+    png.replace_chunk(Chunk::new(chunk_type("PLTE"), plte))?;
+
+    // the twins copy the alpha, so a tRNS chunk exists whenever one is needed. a twin written
+    // over the last transparent entry leaves every entry opaque, the old tRNS has to go then
+    let alpha_len = entries
+        .iter()
+        .rposition(|entry| entry[3] != 255)
+        .map_or(0, |last| last + 1);
+    if alpha_len > 0 {
+        let alpha: Vec<u8> = entries[..alpha_len].iter().map(|entry| entry[3]).collect();
+        png.replace_chunk(Chunk::new(chunk_type("tRNS"), alpha))?;
+    } else {
+        png.remove_chunks_where("tRNS", |_| true);
+    }
+
+    if png.chunk_by_type("hIST").is_some() {
+        let counts = usage(image, entries.len())?;
+        let most = counts.iter().copied().max().unwrap_or(1).max(1);
+        let histogram = Histogram(
+            counts
+                .iter()
+                .map(|count| {
+                    // scaled into u16, used entries stay above zero
+                    if most > u16::MAX as usize {
+                        (count * u16::MAX as usize).div_ceil(most) as u16
+                    } else {
+                        *count as u16
+                    }
+                })
+                .collect(),
+        );
+        png.replace_chunk(Chunk::new(chunk_type("hIST"), histogram.to_bytes()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{Header, SubImage};
+
+    const PALETTE: [Entry; 4] = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 128],
+        [0, 0, 255, 255],
+        [10, 20, 30, 255],
+    ];
+
+    /// an indexed image using the first three palette entries, the last one is unused
+    fn testing_png(bit_depth: u8, with_hist: bool) -> Png {
+        let header = Header {
+            width: 24,
+            height: 16,
+            bit_depth,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        };
+        let row_len = header.row_len(24);
+        let mut data = vec![0u8; row_len * 16];
+        for y in 0..16 {
+            for x in 0..24 {
+                let index = ((x + y) % 3) as u16;
+                set_sample(
+                    &mut data[y * row_len..(y + 1) * row_len],
+                    x,
+                    bit_depth,
+                    index,
+                );
+            }
+        }
+        let image = ImageData {
+            header,
+            passes: vec![SubImage {
+                width: 24,
+                height: 16,
+                data,
+            }],
+            transparency: None,
+        };
+        let new_chunk = |name: &str, data: Vec<u8>| Chunk::new(name.parse().unwrap(), data);
+        let mut chunks = vec![
+            new_chunk("IHDR", header.to_bytes()),
+            new_chunk(
+                "PLTE",
+                PALETTE
+                    .iter()
+                    .flat_map(|entry| entry[..3].to_vec())
+                    .collect(),
+            ),
+            new_chunk("tRNS", vec![255, 128]),
+        ];
+        if with_hist {
+            chunks.push(new_chunk("hIST", vec![0; 8]));
+        }
+        chunks.push(new_chunk("IDAT", image.encode().unwrap()));
+        chunks.push(new_chunk("IEND", Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    /// the color of every pixel
+    fn colors(png: &Png) -> Vec<Entry> {
+        let image = ImageData::decode(png).unwrap();
+        let entries = read_palette(png, &image).unwrap();
+        pixels(&image)
+            .map(|slot| entries[index_at(&image, &slot) as usize])
+            .collect()
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        for (bit_depth, seed) in [(8, None), (4, Some([3; 32])), (2, None)] {
+            let mut png = testing_png(bit_depth, false);
+            let before = colors(&png);
+            let payload = b"palette";
+            embed(&mut png, payload, seed).unwrap();
+            assert_eq!(colors(&png), before, "depth {}", bit_depth);
+            assert_eq!(extract(&png, seed).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn test_twins() {
+        let mut png = testing_png(8, false);
+        embed(&mut png, b"twins", None).unwrap();
+        let image = ImageData::decode(&png).unwrap();
+        let entries = read_palette(&png, &image).unwrap();
+        // the unused entry is reused before appending
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries[3], PALETTE[0]);
+        assert_eq!(
+            png.chunk_by_type("tRNS").unwrap().data(),
+            &[255, 128, 255, 255, 128]
+        );
+        // a second payload reuses the pairs
+        embed(&mut png, b"again", None).unwrap();
+        assert_eq!(png.chunk_by_type("PLTE").unwrap().data().len(), 18);
+        assert_eq!(extract(&png, None).unwrap(), b"again");
+    }
+
+    #[test]
+    fn test_transparent_unused_entry() {
+        let mut png = testing_png(2, false);
+        // every used entry is opaque, only the unused last one is transparent
+        png.replace_chunk(Chunk::new("tRNS".parse().unwrap(), vec![255, 255, 255, 0]))
+            .unwrap();
+        let before = colors(&png);
+        embed(&mut png, b"opaque", None).unwrap();
+        assert!(png.chunk_by_type("tRNS").is_none());
+        assert_eq!(colors(&png), before);
+        assert_eq!(extract(&png, None).unwrap(), b"opaque");
+    }
+
+    #[test]
+    fn test_hist_and_capacity() {
+        let mut png = testing_png(2, true);
+        // 2 bits allow a single twin, over the unused entry
+        assert_eq!(capacity(&png).unwrap(), (128 - 32) / 8);
+        embed(&mut png, b"hist", None).unwrap();
+        let counts: Vec<u16> = png
+            .chunk_by_type("hIST")
+            .unwrap()
+            .data()
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(
+            counts.iter().map(|count| *count as usize).sum::<usize>(),
+            24 * 16
+        );
+        assert_eq!(counts[1], 128);
+        assert!(embed(&mut png, &[1; 13], None).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let png = testing_png(8, false);
+        assert!(extract(&png, None).is_err());
+        let mut png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        assert!(embed(&mut png, b"rgba", None).is_err());
+    }
+}